@@ -1,18 +1,12 @@
 use bytemuck::*;
-use glm::vec3_to_vec4;
-use glm::Mat4;
 use lib3dmol::structures::{atom::AtomType, GetAtom};
-use nalgebra_glm as glm;
-use std::collections::HashMap;
 use wgpu;
 use wgpu_experiments::camera::*;
 use wgpu_experiments::pdb_loader;
 use wgpu_experiments::pipelines::{boxes::*, mesh::MeshPipeline, sphere_billboards::SphereBillboardPipeline};
-use wgpu_experiments::rpdb;
+use wgpu_experiments::scene::Scene;
 use wgpu_experiments::{ApplicationEvent, ApplicationSkeleton, Mesh};
 
-use crate::grid::*;
-
 pub struct ApplicationOptions {
     pub render_molecules: bool,
     pub render_grid: bool,
@@ -45,12 +39,6 @@ impl BoxPipelineInput {
     }
 }
 
-pub struct MoleculePointer {
-    pub bounding_box: rpdb::BoundingBox,
-    pub lods_radii: Vec<f32>,
-    pub lods_vertices: Vec<std::ops::Range<u32>>,
-}
-
 pub struct StructurePointer {}
 
 pub struct Application {
@@ -72,12 +60,8 @@ pub struct Application {
     pub billboards_pipeline: SphereBillboardPipeline,
     pub billboards_bind_group: wgpu::BindGroup,
 
-    molecule_name_id: HashMap<String, usize>,
-
-    molecules_pointers: Vec<MoleculePointer>,
+    scene: Scene,
     atoms_buffer: wgpu::Buffer,
-
-    structure_model_matrices: Vec<Vec<Mat4>>,
     structure_model_matrices_buffer: Vec<wgpu::Buffer>,
 
     merged_buffer: wgpu::Buffer,
@@ -137,81 +121,23 @@ impl Application {
 
         // Open structure file
         let args: Vec<String> = std::env::args().collect();
-        let structure_file_path: &str = &args[1];
-        let structure_folder = std::path::Path::new(structure_file_path);
-        let structure_file = std::fs::read_to_string(structure_file_path).expect("Could not open structure file.");
-        let structure: rpdb::Structure = ron::de::from_str(&structure_file).expect("Could not deserialize structure file.");
-
-        // Load Molecules
-        let mut molecule_name_id: HashMap<String, usize> = HashMap::new();
-        let mut molecules = Vec::new();
-        let mut molecules_pointers = Vec::new();
-        let mut structure_model_matrices = Vec::new();
-
-        let mut atoms = Vec::new();
-        let mut atoms_sum = 0u32;
-        let mut molecules_num = 0;
-        for (name, matrix) in structure.names.iter().zip(structure.model_matrices.iter()) {
-            let molecule_loaded = molecule_name_id.contains_key(name);
-            if !molecule_loaded {
-                // Load a molecule
-                let molecule_file_str = std::fs::read_to_string(structure_folder.with_file_name(name.to_string() + ".ron"))
-                    .expect("Could not open structure file.");
-                let molecule: rpdb::Molecule = ron::de::from_str(&molecule_file_str).expect("Could not parse molecule.");
-
-                let mut lods_vertices: Vec<std::ops::Range<u32>> = Vec::new();
-                let mut lods_radii = Vec::new();
-                for lod in molecule.lods() {
-                    lods_radii.push(lod.max_radius());
-                    for atom in lod.atoms() {
-                        atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
-                    }
-                    lods_vertices.push(atoms_sum * 3..(atoms_sum + lod.atoms().len() as u32) * 3);
-                    atoms_sum += lod.atoms().len() as u32;
-                }
-
-                molecules_pointers.push(MoleculePointer {
-                    bounding_box: molecule.bounding_box,
-                    lods_radii,
-                    lods_vertices,
-                });
-
-                molecules.push(molecule);
-                molecule_name_id.insert(name.clone(), molecules_num);
-                molecules_num += 1;
-                structure_model_matrices.push(Vec::new());
-            }
-
-            structure_model_matrices[molecule_name_id[name]].push(*matrix);
-        }
+        let scene = Scene::load(std::path::Path::new(&args[1]));
 
-        let atoms_buffer =
-            device.create_buffer_with_data(cast_slice(&atoms), wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_DST);
+        let atoms_buffer = device.create_buffer_with_data(
+            cast_slice(&scene.atoms),
+            wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_DST,
+        );
 
         let mut structure_model_matrices_buffer = Vec::new();
-        for (i, structure_molecule) in structure_model_matrices.iter().enumerate() {
-            let mut matrices = Vec::new();
-            for m in structure_molecule {
-                matrices.extend_from_slice(m.as_slice());
-            }
-            structure_model_matrices_buffer[i] =
-                device.create_buffer_with_data(cast_slice(&matrices), wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_DST);
+        for molecule in &scene.molecules {
+            structure_model_matrices_buffer.push(device.create_buffer_with_data(
+                cast_slice(&molecule.model_matrices_packed()),
+                wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_DST,
+            ));
         }
 
         // Build merged buffer
-        let mut merged_buffer = Vec::new();
-        for (molecule_type_id, molecule_model_matrices) in structure_model_matrices.iter().enumerate() {
-            let molecule = &molecules[molecule_type_id];
-            for molecule_model_matrix in molecule_model_matrices {
-                for atom in molecule.lods()[0].atoms() {
-                    let mut atom_position = vec3_to_vec4(&atom.xyz());
-                    atom_position.w = 1.0;
-                    let atom_position = molecule_model_matrix * atom_position;
-                    merged_buffer.extend_from_slice(&[atom_position.x, atom_position.y, atom_position.z, atom.w]);
-                }
-            }
-        }
-
+        let merged_buffer = scene.world_atoms(0);
         let merged_buffer_len = (merged_buffer.len() / 4) as u32;
         let merged_buffer = device.create_buffer_with_data(
             cast_slice(&merged_buffer),
@@ -257,12 +183,8 @@ impl Application {
             billboards_pipeline,
             billboards_bind_group,
 
-            molecule_name_id,
-
-            molecules_pointers,
+            scene,
             atoms_buffer,
-
-            structure_model_matrices,
             structure_model_matrices_buffer,
 
             merged_buffer,
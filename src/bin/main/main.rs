@@ -1,5 +1,4 @@
 mod application;
-mod grid;
 
 use wgpu_experiments::{ApplicationEvent, ApplicationSkeleton};
 
@@ -1,14 +1,11 @@
 use bytemuck::*;
-use glm::Mat4;
-use nalgebra_glm as glm;
-use std::collections::HashMap;
 use wgpu;
 use wgpu_experiments::camera::*;
 use wgpu_experiments::pipelines::{
     boxes::BoxDepthPipeline,
     sphere_billboards::{SphereBillboardInstancedDepthPipeline, SphereBillboardInstancedPipeline},
 };
-use wgpu_experiments::rpdb;
+use wgpu_experiments::scene::Scene;
 use wgpu_experiments::{ApplicationEvent, ApplicationSkeleton};
 
 pub struct ApplicationOptions {
@@ -17,12 +14,6 @@ pub struct ApplicationOptions {
     pub render_output: bool,
 }
 
-pub struct MoleculePointer {
-    pub bounding_box: rpdb::BoundingBox,
-    pub lods_radii: Vec<f32>,
-    pub lods_vertices: Vec<std::ops::Range<u32>>,
-}
-
 pub struct StructurePointer {}
 
 pub struct Application {
@@ -48,12 +39,8 @@ pub struct Application {
     box_depth_pipeline_write: BoxDepthPipeline,
     box_depth_pipeline_read: BoxDepthPipeline,
 
-    molecule_name_id: HashMap<String, usize>,
-
-    molecules_pointers: Vec<MoleculePointer>,
+    scene: Scene,
     atoms_buffer: wgpu::Buffer,
-
-    structure_model_matrices: Vec<Vec<Mat4>>,
     structure_model_matrices_buffer: Vec<wgpu::Buffer>,
 
     aabbs_len: u64,
@@ -114,59 +101,10 @@ impl Application {
 
         // Open structure file
         let args: Vec<String> = std::env::args().collect();
-        let structure_file_path: &str = &args[1];
-        let structure_folder = std::path::Path::new(structure_file_path);
-        let structure_file = std::fs::read_to_string(structure_file_path).expect("Could not open structure file.");
-        let structure: rpdb::Structure = ron::de::from_str(&structure_file).expect("Could not deserialize structure file.");
-
-        // Load Molecules
-        let mut molecule_name_id: HashMap<String, usize> = HashMap::new();
-        let mut molecules = Vec::new();
-        let mut molecules_pointers = Vec::new();
-        let mut structure_model_matrices = Vec::new();
-
-        let mut atoms = Vec::new();
-        let mut atoms_sum = 0u32;
-        let mut molecules_num = 0;
-        for (name, matrix) in structure.names.iter().zip(structure.model_matrices.iter()) {
-            let molecule_loaded = molecule_name_id.contains_key(name);
-            if !molecule_loaded {
-                // Load a molecule
-                let molecule_file_str = std::fs::read_to_string(structure_folder.with_file_name(name.to_string() + ".ron"))
-                    .expect("Could not open structure file.");
-                let molecule: rpdb::Molecule = ron::de::from_str(&molecule_file_str).expect("Could not parse molecule.");
-
-                let mut lods_vertices: Vec<std::ops::Range<u32>> = Vec::new();
-                let mut lods_radii = Vec::new();
-                for lod in molecule.lods() {
-                    lods_radii.push(lod.max_radius());
-                    let mut new_vertices = 0;
-                    for atom in lod.atoms() {
-                        atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
-                        atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
-                        atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
-                        new_vertices += 3;
-                    }
-                    lods_vertices.push(atoms_sum..atoms_sum + new_vertices);
-                    atoms_sum += new_vertices;
-                }
-
-                molecules_pointers.push(MoleculePointer {
-                    bounding_box: molecule.bounding_box,
-                    lods_radii,
-                    lods_vertices,
-                });
+        let scene = Scene::load(std::path::Path::new(&args[1]));
 
-                molecules.push(molecule);
-                molecule_name_id.insert(name.clone(), molecules_num);
-                molecules_num += 1;
-                structure_model_matrices.push(Vec::new());
-            }
-
-            structure_model_matrices[molecule_name_id[name]].push(*matrix);
-        }
-
-        let atoms_buffer = device.create_buffer_with_data(cast_slice(&atoms), wgpu::BufferUsage::VERTEX);
+        // Every billboard is made of three vertices
+        let atoms_buffer = device.create_buffer_with_data(cast_slice(&scene.atoms_per_vertex(3)), wgpu::BufferUsage::VERTEX);
 
         println!("Pipeline");
         let billboards_pipeline = SphereBillboardInstancedPipeline::new(&device);
@@ -177,17 +115,11 @@ impl Application {
         let mut billboards_bind_groups = Vec::new();
         let mut billboards_depth_bind_groups = Vec::new();
         let mut structure_model_matrices_buffer = Vec::new();
-        let mut aabbs_matrices = Vec::new();
-        for (i, structure_molecule) in structure_model_matrices.iter().enumerate() {
-            let mut matrices = Vec::new();
-            for m in structure_molecule {
-                matrices.extend_from_slice(m.as_slice());
-
-                let aabb_matrix = m * glm::scaling(&(molecules[i].bounding_box.max - molecules[i].bounding_box.min));
-                aabbs_matrices.extend_from_slice(aabb_matrix.as_slice());
-            }
-            structure_model_matrices_buffer
-                .push(device.create_buffer_with_data(cast_slice(&matrices), wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST));
+        for molecule in &scene.molecules {
+            structure_model_matrices_buffer.push(device.create_buffer_with_data(
+                cast_slice(&molecule.model_matrices_packed()),
+                wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            ));
 
             billboards_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: None,
@@ -220,9 +152,9 @@ impl Application {
             }));
         }
 
-        let aabbs_len = (aabbs_matrices.len() / 16) as u64;
+        let aabbs_len = scene.instances_len() as u64;
         let aabbs_matrices = device.create_buffer_with_data(
-            cast_slice(&aabbs_matrices),
+            cast_slice(&scene.aabb_matrices()),
             wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
         );
         let aabbs_fragments = device.create_buffer(&wgpu::BufferDescriptor {
@@ -251,12 +183,8 @@ impl Application {
             box_depth_pipeline_write,
             box_depth_pipeline_read,
 
-            molecule_name_id,
-
-            molecules_pointers,
+            scene,
             atoms_buffer,
-
-            structure_model_matrices,
             structure_model_matrices_buffer,
 
             aabbs_len,
@@ -328,13 +256,11 @@ impl ApplicationSkeleton for Application {
             rpass.set_pipeline(&self.billboards_depth_pipeline.pipeline);
             rpass.set_vertex_buffer(0, self.atoms_buffer.slice(0..0));
 
-            for (molecule_index, molecule) in self.molecules_pointers.iter().enumerate() {
-                if molecule.lods_vertices[0].end - molecule.lods_vertices[0].start == 156 {
+            for (molecule_index, molecule) in self.scene.molecules.iter().enumerate() {
+                let lods_vertices = molecule.lods_vertices(3);
+                if lods_vertices[0].end - lods_vertices[0].start == 156 {
                     rpass.set_bind_group(0, &self.billboards_depth_bind_groups[molecule_index], &[]);
-                    rpass.draw(
-                        molecule.lods_vertices[0].clone(),
-                        0..self.structure_model_matrices[molecule_index].len() as u32,
-                    );
+                    rpass.draw(lods_vertices[0].clone(), 0..molecule.model_matrices.len() as u32);
                 }
             }
         }
@@ -382,15 +308,11 @@ impl ApplicationSkeleton for Application {
             rpass.set_pipeline(&self.billboards_pipeline.pipeline);
             rpass.set_vertex_buffer(0, self.atoms_buffer.slice(0..0));
 
-            for (molecule_index, molecule) in self.molecules_pointers.iter().enumerate() {
+            for (molecule_index, molecule) in self.scene.molecules.iter().enumerate() {
                 rpass.set_bind_group(0, &self.billboards_bind_groups[molecule_index], &[]);
-                // println!("{:?}",molecule.lods_vertices[0].end - molecule.lods_vertices[0].start);
-                if molecule.lods_vertices[0].end - molecule.lods_vertices[0].start == 156 {
-                    // println!("yes");
-                    rpass.draw(
-                        molecule.lods_vertices[0].clone(),
-                        0..self.structure_model_matrices[molecule_index].len() as u32,
-                    );
+                let lods_vertices = molecule.lods_vertices(3);
+                if lods_vertices[0].end - lods_vertices[0].start == 156 {
+                    rpass.draw(lods_vertices[0].clone(), 0..molecule.model_matrices.len() as u32);
                 }
             }
         }
@@ -1,12 +1,11 @@
 use crate::small_molecules_pipeline::*;
 
 use bytemuck::*;
-use nalgebra_glm::{scaling, vec2, vec4, zero, Mat4};
-use std::collections::HashMap;
+use nalgebra_glm::{scaling, vec2, vec4, zero};
 use wgpu;
 use wgpu_experiments::camera::*;
 use wgpu_experiments::pipelines::depth_conversion::DepthConversionPipeline;
-use wgpu_experiments::rpdb;
+use wgpu_experiments::scene::Scene;
 use wgpu_experiments::{ApplicationEvent, ApplicationSkeleton};
 
 pub struct ApplicationOptions {
@@ -15,12 +14,6 @@ pub struct ApplicationOptions {
     pub render_output: bool,
 }
 
-pub struct MoleculePointer {
-    pub bounding_box: rpdb::BoundingBox,
-    pub lods_radii: Vec<f32>,
-    pub lods_vertices: Vec<std::ops::Range<u32>>,
-}
-
 pub struct Application {
     width: u32,
     height: u32,
@@ -46,12 +39,10 @@ pub struct Application {
     pipeline_depth: SmallMoleculesPipeline,
     depth_conversion_pipeline: DepthConversionPipeline,
 
-    molecule_name_id: HashMap<String, usize>,
-    molecules_pointers: Vec<MoleculePointer>,
+    scene: Scene,
     molecules_ubos: Vec<Option<wgpu::Buffer>>,
     atoms_buffer: wgpu::Buffer,
 
-    structure_model_matrices: Vec<Vec<Mat4>>,
     structure_model_matrices_buffer: Vec<wgpu::Buffer>,
 
     depth_only: bool,
@@ -130,90 +121,42 @@ impl Application {
 
         // Open structure file
         let args: Vec<String> = std::env::args().collect();
-        let structure_file_path: &str = &args[1];
-        let structure_folder = std::path::Path::new(structure_file_path);
-        let structure_file = std::fs::read_to_string(structure_file_path).expect("Could not open structure file.");
-        let structure: rpdb::Structure = ron::de::from_str(&structure_file).expect("Could not deserialize structure file.");
-
-        // Load Molecules
-        let mut molecule_name_id: HashMap<String, usize> = HashMap::new();
-        let mut molecules = Vec::new();
-        let mut molecules_pointers = Vec::new();
-        let mut molecules_ubos = Vec::new();
-        let mut structure_model_matrices = Vec::new();
-
-        let mut atoms = Vec::new();
-        let mut atoms_sum = 0u32;
-        let mut molecules_num = 0;
-        for (name, matrix) in structure.names.iter().zip(structure.model_matrices.iter()) {
-            let molecule_loaded = molecule_name_id.contains_key(name);
-            if !molecule_loaded {
-                // Load a molecule
-                let molecule_file_str = std::fs::read_to_string(structure_folder.with_file_name(name.to_string() + ".ron"))
-                    .expect("Could not open structure file.");
-                let molecule: rpdb::Molecule = ron::de::from_str(&molecule_file_str).expect("Could not parse molecule.");
-
-                let mut lods_vertices: Vec<std::ops::Range<u32>> = Vec::new();
-                let mut lods_radii = Vec::new();
-                for lod in molecule.lods() {
-                    lods_radii.push(lod.max_radius());
-                    let mut new_vertices = 0;
-                    for atom in lod.atoms() {
-                        atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
-                        atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
-                        atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
-                        new_vertices += 3;
-                    }
-                    lods_vertices.push(atoms_sum..atoms_sum + new_vertices);
-                    atoms_sum += new_vertices;
-                }
-
-                molecules_pointers.push(MoleculePointer {
-                    bounding_box: molecule.bounding_box,
-                    lods_radii,
-                    lods_vertices,
-                });
+        let scene = Scene::load(std::path::Path::new(&args[1]));
 
-                if molecule.lods()[0].atoms().len() == 52 {
-                    let mut positions = [vec4(0.0, 0.0, 0.0, 0.0); 64];
-                    for (i, position) in molecule.lods()[0].atoms().iter().enumerate() {
-                        positions[i] = *position;
-                    }
-                    let scale = molecule.bounding_box.max - molecule.bounding_box.min;
-                    let molecule_ubo = MoleculeUbo {
-                        positions,
-                        aabb_scale: vec4(scale.x, scale.y, scale.z, 1.0),
-                        count: molecule.lods()[0].atoms().len() as u32,
-                    };
-                    let molecule_buffer = device.create_buffer_with_data(cast_slice(&[molecule_ubo]), wgpu::BufferUsage::UNIFORM);
-                    molecules_ubos.push(Some(molecule_buffer));
-                } else {
-                    molecules_ubos.push(None);
+        let mut molecules_ubos = Vec::new();
+        for scene_molecule in &scene.molecules {
+            let molecule = &scene_molecule.molecule;
+            if molecule.lods()[0].atoms().len() == 52 {
+                let mut positions = [vec4(0.0, 0.0, 0.0, 0.0); 64];
+                for (i, position) in molecule.lods()[0].atoms().iter().enumerate() {
+                    positions[i] = *position;
                 }
-
-                molecules.push(molecule);
-                molecule_name_id.insert(name.clone(), molecules_num);
-                molecules_num += 1;
-                structure_model_matrices.push(Vec::new());
+                let scale = molecule.bounding_box.max - molecule.bounding_box.min;
+                let molecule_ubo = MoleculeUbo {
+                    positions,
+                    aabb_scale: vec4(scale.x, scale.y, scale.z, 1.0),
+                    count: molecule.lods()[0].atoms().len() as u32,
+                };
+                let molecule_buffer = device.create_buffer_with_data(cast_slice(&[molecule_ubo]), wgpu::BufferUsage::UNIFORM);
+                molecules_ubos.push(Some(molecule_buffer));
+            } else {
+                molecules_ubos.push(None);
             }
-
-            structure_model_matrices[molecule_name_id[name]].push(*matrix);
         }
 
-        let atoms_buffer = device.create_buffer_with_data(cast_slice(&atoms), wgpu::BufferUsage::VERTEX);
+        // Every billboard is made of three vertices
+        let atoms_buffer = device.create_buffer_with_data(cast_slice(&scene.atoms_per_vertex(3)), wgpu::BufferUsage::VERTEX);
 
         let pipeline = SmallMoleculesPipeline::new(&device, false);
         let pipeline_depth = SmallMoleculesPipeline::new(&device, true);
         let depth_conversion_pipeline = DepthConversionPipeline::new(&device);
 
         let mut structure_model_matrices_buffer = Vec::new();
-        for (i, structure_molecule) in structure_model_matrices.iter().enumerate() {
-            let mut matrices = Vec::new();
-            for m in structure_molecule {
-                matrices.extend_from_slice(m.as_slice());
-            }
-            structure_model_matrices_buffer
-                .push(device.create_buffer_with_data(cast_slice(&matrices), wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST));
+        for molecule in &scene.molecules {
+            structure_model_matrices_buffer.push(device.create_buffer_with_data(
+                cast_slice(&molecule.model_matrices_packed()),
+                wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            ));
         }
 
         Self {
@@ -240,12 +183,10 @@ impl Application {
             pipeline_depth,
             depth_conversion_pipeline,
 
-            molecule_name_id,
-            molecules_pointers,
+            scene,
             molecules_ubos,
             atoms_buffer,
 
-            structure_model_matrices,
             structure_model_matrices_buffer,
 
             depth_only: false,
@@ -294,7 +235,7 @@ impl ApplicationSkeleton for Application {
         }
 
         let mut bind_groups = Vec::new();
-        for (molecule_index, molecule) in self.molecules_pointers.iter().enumerate() {
+        for (molecule_index, molecule) in self.scene.molecules.iter().enumerate() {
             if let Some(ref molecule_ubo) = self.molecules_ubos[molecule_index] {
                 let bind_group = if self.depth_only {
                     self.pipeline_depth.create_bind_group(
@@ -354,10 +295,10 @@ impl ApplicationSkeleton for Application {
                 rpass.set_mesh_pipeline(&self.pipeline.pipeline);
             }
 
-            for (molecule_index, molecule) in self.molecules_pointers.iter().enumerate() {
+            for (molecule_index, molecule) in self.scene.molecules.iter().enumerate() {
                 if let Some(ref molecule_ubo) = self.molecules_ubos[molecule_index] {
                     rpass.set_bind_group(0, bind_groups[molecule_index].as_ref().unwrap(), &[]);
-                    let tasks_count = self.structure_model_matrices[molecule_index].len();
+                    let tasks_count = molecule.model_matrices.len();
                     // let tasks_count = tasks_count - (tasks_count % 32);
                     // let tasks_count = tasks_count / 32;
                     rpass.draw_mesh_tasks(tasks_count as u32);
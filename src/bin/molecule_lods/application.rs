@@ -1,16 +1,23 @@
 use bytemuck::*;
 use nalgebra_glm::*;
-use ron::de::from_str;
 use wgpu;
 use wgpu_experiments::camera::*;
 use wgpu_experiments::kmeans::*;
+use wgpu_experiments::lod;
 use wgpu_experiments::pdb_loader;
 use wgpu_experiments::pipelines::sphere_billboards::SphereBillboardPipeline;
 use wgpu_experiments::rpdb;
 use wgpu_experiments::{ApplicationEvent, ApplicationSkeleton};
 
+const FOV: f32 = 0.785398163;
+const NEAR: f32 = 0.1;
+// LODs are switched automatically once their error is smaller than this on screen
+const MAX_PIXEL_ERROR: f32 = 1.0;
+
 pub struct ApplicationOptions {
     pub selected_lod: u32,
+    /// Picks the LOD from the camera distance, the Numpad keys switch back to manual selection.
+    pub automatic_lod: bool,
 }
 
 pub struct Application {
@@ -32,11 +39,15 @@ pub struct Application {
     pub billboards_bind_group: wgpu::BindGroup,
 
     lods: Vec<std::ops::Range<u32>>,
+    molecule: rpdb::Molecule,
 }
 
 impl Application {
     pub async fn new(width: u32, height: u32, surface: &wgpu::Surface) -> Self {
-        let options = ApplicationOptions { selected_lod: 0 };
+        let options = ApplicationOptions {
+            selected_lod: 0,
+            automatic_lod: true,
+        };
 
         // let adapter = &wgpu::Adapter::enumerate(wgpu::BackendBit::PRIMARY)[1];
         let adapter = wgpu::Adapter::request(
@@ -61,7 +72,7 @@ impl Application {
             .await;
 
         let aspect = width as f32 / height as f32;
-        let mut camera = RotationCamera::new(aspect, 0.785398163, 0.1);
+        let mut camera = RotationCamera::new(aspect, FOV, NEAR);
         let camera_buffer = device.create_buffer_with_data(
             cast_slice(&[camera.ubo()]),
             wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
@@ -81,8 +92,7 @@ impl Application {
 
         let args: Vec<String> = std::env::args().collect();
         let molecule_file_path: &str = &args[1];
-        let molecule_ron = std::fs::read_to_string(molecule_file_path).unwrap();
-        let molecule: rpdb::Molecule = from_str(&molecule_ron).unwrap();
+        let molecule = rpdb::Molecule::load(std::path::Path::new(molecule_file_path));
 
         let mut atoms = Vec::new();
         let mut lods: Vec<std::ops::Range<u32>> = Vec::new();
@@ -141,6 +151,7 @@ impl Application {
             billboards_bind_group,
 
             lods,
+            molecule,
         }
     }
 
@@ -166,24 +177,34 @@ impl ApplicationSkeleton for Application {
                     match key {
                         VirtualKeyCode::Numpad0 => {
                             self.options.selected_lod = 0;
+                            self.options.automatic_lod = false;
                         }
                         VirtualKeyCode::Numpad1 => {
                             self.options.selected_lod = 1;
+                            self.options.automatic_lod = false;
                         }
                         VirtualKeyCode::Numpad2 => {
                             self.options.selected_lod = 2;
+                            self.options.automatic_lod = false;
                         }
                         VirtualKeyCode::Numpad3 => {
                             self.options.selected_lod = 3;
+                            self.options.automatic_lod = false;
                         }
                         VirtualKeyCode::Numpad4 => {
                             self.options.selected_lod = 4;
+                            self.options.automatic_lod = false;
                         }
                         VirtualKeyCode::Numpad5 => {
                             self.options.selected_lod = 5;
+                            self.options.automatic_lod = false;
                         }
                         VirtualKeyCode::Numpad6 => {
                             self.options.selected_lod = 6;
+                            self.options.automatic_lod = false;
+                        }
+                        VirtualKeyCode::A => {
+                            self.options.automatic_lod = true;
                         }
                         _ => {}
                     };
@@ -196,6 +217,18 @@ impl ApplicationSkeleton for Application {
     }
 
     fn render(&mut self, frame: &wgpu::TextureView) {
+        if self.options.automatic_lod {
+            let lod = lod::select_lod(
+                self.molecule.lods(),
+                self.camera.distance(),
+                distance(&self.molecule.bounding_box().min, &self.molecule.bounding_box().max) / 2.0,
+                NEAR,
+                FOV,
+                self.height as f32,
+                MAX_PIXEL_ERROR,
+            );
+            self.options.selected_lod = lod as u32;
+        }
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
         {
@@ -229,7 +262,8 @@ impl ApplicationSkeleton for Application {
 
             rpass.set_pipeline(&self.billboards_pipeline.pipeline);
             rpass.set_bind_group(0, &self.billboards_bind_group, &[]);
-            rpass.draw(self.lods[self.options.selected_lod as usize].clone(), 0..1);
+            let selected_lod = (self.options.selected_lod as usize).min(self.lods.len() - 1);
+            rpass.draw(self.lods[selected_lod].clone(), 0..1);
         }
 
         self.queue.submit(&[encoder.finish()]);
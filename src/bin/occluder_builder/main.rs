@@ -0,0 +1,239 @@
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use wgpu_experiments::rpdb;
+use wgpu_experiments::voxel::*;
+
+const USAGE: &str = "Usage: occluder_builder <molecule.ron|.rpdb>... [-o output.ron|.rpdb | --in-place]
+       [--size N] [--boxes N] [--min-volume N] [--triangles N] [--contours voxels|distance]
+       Voxelizes the first LOD of every molecule and stores it with its box and planar occluders next to the
+       input as <name>.occluders.<ext>, in the -o file for a single input, or in the input itself with --in-place.
+       --boxes 0 or --triangles 0 skip that kind of occluders. Reports how much of the silhouette they hide, and
+       how much of what they cover lies outside it (false occlusion).
+       --contours picks how the planar occluder slices are outlined, voxel outlines by default or smooth
+       iso-contours of the distance field.";
+
+struct Options {
+    inputs: Vec<PathBuf>,
+    /// Output file, only valid for a single input
+    output: Option<PathBuf>,
+    /// Overwrite the inputs instead of writing `<name>.occluders.<ext>` files
+    in_place: bool,
+    /// Voxels along every axis of the grid
+    size: i32,
+    /// Maximum number of box occluders
+    boxes: usize,
+    /// Smallest box occluder (in voxels)
+    min_volume: usize,
+    /// Maximum number of planar occluder triangles
+    triangles: usize,
+    /// Outlines of the planar occluder slices
+    contours: SliceContours,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    std::process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut in_place = false;
+    let mut size = 256;
+    let mut boxes = 64;
+    let mut min_volume = 8;
+    let mut triangles = 10000;
+    let mut contours = SliceContours::Voxels;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().unwrap_or_else(|| usage_error(&format!("Missing value for {}", name)));
+
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(value("--output"))),
+            "--in-place" => in_place = true,
+            "--size" => size = value("--size").parse().unwrap_or_else(|_| usage_error("--size expects an integer")),
+            "--boxes" => {
+                boxes = value("--boxes")
+                    .parse()
+                    .unwrap_or_else(|_| usage_error("--boxes expects an integer"))
+            }
+            "--min-volume" => {
+                min_volume = value("--min-volume")
+                    .parse()
+                    .unwrap_or_else(|_| usage_error("--min-volume expects an integer"))
+            }
+            "--triangles" => {
+                triangles = value("--triangles")
+                    .parse()
+                    .unwrap_or_else(|_| usage_error("--triangles expects an integer"))
+            }
+            "--contours" => {
+                contours = match value("--contours").as_str() {
+                    "voxels" => SliceContours::Voxels,
+                    "distance" => SliceContours::Distance,
+                    other => usage_error(&format!("Unknown contours {}", other)),
+                }
+            }
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if inputs.is_empty() {
+        usage_error("Missing input molecules");
+    }
+    if size <= 0 {
+        usage_error("--size must be positive");
+    }
+    if output.is_some() && (in_place || inputs.len() != 1) {
+        usage_error("--output needs exactly one input and no --in-place");
+    }
+    if output.as_ref() == inputs.first() {
+        usage_error("--output is the input, pass --in-place to overwrite it");
+    }
+
+    Options {
+        inputs,
+        output,
+        in_place,
+        size,
+        boxes,
+        min_volume,
+        triangles,
+        contours,
+    }
+}
+
+/// Where the molecule with occluders is written, never the input unless `--in-place` is passed.
+fn output_path(options: &Options, input: &Path) -> PathBuf {
+    if let Some(output) = &options.output {
+        return output.clone();
+    }
+    if options.in_place {
+        return input.to_path_buf();
+    }
+
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    match input.extension() {
+        Some(extension) => input.with_file_name(format!("{}.occluders.{}", stem, extension.to_string_lossy())),
+        None => input.with_file_name(format!("{}.occluders", stem)),
+    }
+}
+
+fn triangles_area(triangles: &[glm::Vec3]) -> f32 {
+    triangles
+        .chunks_exact(3)
+        .map(|t| glm::length(&glm::cross(&(t[1] - t[0]), &(t[2] - t[0]))) / 2.0)
+        .sum()
+}
+
+/// Builds the occluders of one molecule, returning the report printed once the file is done.
+fn build(options: &Options, input: &Path, output: &Path) -> Result<String, String> {
+    let mut molecule = rpdb::Molecule::try_load(input).map_err(|e| e.to_string())?;
+    let lod_atoms = match molecule.lods().first() {
+        Some(lod) if !lod.atoms().is_empty() => lod.atoms(),
+        _ => return Err(format!("{}: no atoms", input.display())),
+    };
+
+    let start = std::time::Instant::now();
+
+    // The grid centers the atoms, occluders are moved back to the molecule coordinates
+    let mut atoms = lod_atoms.to_vec();
+    let mut voxel_grid = VoxelGrid::new(
+        &mut atoms,
+        &VoxelizationOptions {
+            size: options.size,
+            ..Default::default()
+        },
+    );
+    let offset = lod_atoms[0].xyz() - atoms[0].xyz();
+
+    let box_occluders = voxel_grid.get_box_occluders(options.boxes, options.min_volume);
+    let boxes_volume: usize = box_occluders.iter().map(box_volume).sum();
+    let boxes_coverage = boxes_volume as f32 / voxel_grid.solid_count().max(1) as f32;
+    let boxes_ws: Vec<(glm::Vec3, glm::Vec3)> = box_occluders.iter().map(|voxel_box| voxel_grid.box_to_ws(voxel_box)).collect();
+
+    let triangles_ws: Vec<glm::Vec3> = if options.triangles > 0 {
+        voxel_grid
+            .get_planar_occluders_with(options.triangles, options.contours)
+            .iter()
+            .map(|vertex| vertex.xyz())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let triangles_area = triangles_area(&triangles_ws);
+
+    // Share of the silhouette the occluders hide, averaged over the views
+    let evaluator = occlusion::OcclusionEvaluator::new(&voxel_grid, &occlusion::OcclusionOptions::default());
+    let box_triangles: Vec<glm::Vec3> = boxes_ws.iter().flat_map(|(min, max)| occlusion::box_triangles(min, max)).collect();
+    let boxes_occlusion = evaluator.mean_occlusion(&box_triangles);
+    let triangles_occlusion = evaluator.mean_occlusion(&triangles_ws);
+
+    let report = format!(
+        "{} -> {}: {}\n  {} boxes covering {:.1}% of the volume, hiding {:.1}% ({:.1}% false)\n  {} triangles of {:.1} Å², hiding {:.1}% ({:.1}% false)\n  done in {:.2}s\n",
+        input.display(),
+        output.display(),
+        voxel_grid,
+        boxes_ws.len(),
+        boxes_coverage * 100.0,
+        boxes_occlusion.hidden * 100.0,
+        boxes_occlusion.false_occlusion * 100.0,
+        triangles_ws.len() / 3,
+        triangles_area,
+        triangles_occlusion.hidden * 100.0,
+        triangles_occlusion.false_occlusion * 100.0,
+        start.elapsed().as_secs_f32()
+    );
+
+    let boxes = boxes_ws
+        .iter()
+        .map(|(min, max)| rpdb::BoundingBox {
+            min: min + offset,
+            max: max + offset,
+        })
+        .collect();
+    let triangles = triangles_ws.iter().map(|vertex| vertex + offset).collect();
+
+    molecule.occluders = Some(rpdb::Occluders {
+        grid_size: options.size,
+        boxes,
+        boxes_coverage,
+        triangles,
+        triangles_area,
+    });
+
+    // Binary container for `.rpdb` outputs, RON otherwise
+    molecule.try_save(output).map_err(|e| e.to_string())?;
+
+    Ok(report)
+}
+
+fn main() {
+    let options = parse_args();
+
+    let failed: Vec<String> = options
+        .inputs
+        .par_iter()
+        .filter_map(|input| {
+            let output = output_path(&options, input);
+
+            match build(&options, input, &output) {
+                Ok(report) => {
+                    print!("{}", report);
+                    None
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    Some(e)
+                }
+            }
+        })
+        .collect();
+
+    println!("{} built, {} failed", options.inputs.len() - failed.len(), failed.len());
+    if !failed.is_empty() {
+        std::process::exit(1);
+    }
+}
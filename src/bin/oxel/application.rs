@@ -9,13 +9,13 @@ use wgpu_experiments::camera::*;
 use wgpu_experiments::pdb_loader;
 use wgpu_experiments::pipelines::{boxes::*, mesh::MeshPipeline, sphere_billboards::SphereBillboardPipeline, triangles::TrianglesPipeline};
 use wgpu_experiments::rpdb;
+use wgpu_experiments::voxel::*;
 use wgpu_experiments::{ApplicationEvent, ApplicationSkeleton, Mesh};
 
-use crate::grid::*;
-
 pub struct ApplicationOptions {
     pub render_molecules: bool,
     pub render_grid: bool,
+    pub render_surface: bool,
     pub render_aabbs: bool,
 }
 
@@ -88,6 +88,12 @@ pub struct Application {
     pub grid: BoxPipelineInput,
     pub grid_bind_group: wgpu::BindGroup,
 
+    // Surface of the grid
+    pub mesh_pipeline: MeshPipeline,
+    pub surface_instance: wgpu::Buffer,
+    pub surface_bind_group: wgpu::BindGroup,
+    pub surface_meshes: Vec<Mesh>,
+
     // Planar Occluders
     pub planar_occluders_pipeline: TrianglesPipeline,
     pub planar_occluders_bind_group: wgpu::BindGroup,
@@ -101,6 +107,7 @@ impl Application {
         let options = ApplicationOptions {
             render_molecules: true,
             render_grid: false,
+            render_surface: false,
             render_aabbs: false,
         };
 
@@ -146,9 +153,7 @@ impl Application {
 
         // Open structure file
         let args: Vec<String> = std::env::args().collect();
-        let molecule_file_path: &str = &args[1];
-        let molecule_ron = std::fs::read_to_string(molecule_file_path).unwrap();
-        let molecule: rpdb::Molecule = ron::de::from_str(&molecule_ron).unwrap();
+        let molecule = rpdb::Molecule::load(std::path::Path::new(&args[1]));
 
         let mut atoms = Vec::new();
         for atom in molecule.lods[0].atoms() {
@@ -188,7 +193,15 @@ impl Application {
         println!("Loading done.");
 
         let mut voxel_grid_atoms: Vec<glm::Vec4> = molecule.lods[0].atoms().to_vec();
-        let mut voxel_grid = VoxelGrid::new(&mut voxel_grid_atoms);
+        let mut voxel_grid = VoxelGrid::new(
+            &mut voxel_grid_atoms,
+            &VoxelizationOptions {
+                size: 256,
+                sampling: Sampling::Center,
+                fill_interior: false,
+            },
+        );
+        println!("Voxel grid: {}", voxel_grid);
 
         let box_pipeline_line = BoxPipeline::new(&device, BoxRendering::Line);
         let box_pipeline_filled = BoxPipeline::new(&device, BoxRendering::Filled);
@@ -248,7 +261,7 @@ impl Application {
                     for z in 0..voxel_grid.size {
                         let index = glm::vec3(x as i32, y as i32, z as i32);
 
-                        if voxel_grid.voxels[voxel_grid.to_1d(index)] {
+                        if voxel_grid.voxels.get(voxel_grid.to_1d(index)) {
                             let position = voxel_grid.to_ws(index);
                             let size = voxel_grid.voxel_size;
                             let color = glm::vec3(0.0, 0.0, 1.0);
@@ -304,6 +317,38 @@ impl Application {
 
         println!("Grid buffers done.");
 
+        let surface = voxel_grid.surface_mesh();
+        println!(
+            "Surface: {} vertices, {} triangles",
+            surface.vertices.len(),
+            surface.triangles_len()
+        );
+        let surface_meshes = Mesh::from_surface_mesh(&device, &surface);
+
+        // A single instance, the surface is already in world space
+        let mesh_pipeline = MeshPipeline::new(&device);
+        let surface_instance = device.create_buffer_with_data(cast_slice(&[0.0f32, 0.0, 0.0, 1.0]), wgpu::BufferUsage::STORAGE_READ);
+        let surface_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &mesh_pipeline.bind_group_layout,
+            bindings: &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::Buffer {
+                        buffer: &camera_buffer,
+                        range: 0..std::mem::size_of::<CameraUbo>() as u64,
+                    },
+                },
+                Binding {
+                    binding: 1,
+                    resource: BindingResource::Buffer {
+                        buffer: &surface_instance,
+                        range: 0..(4 * std::mem::size_of::<f32>()) as u64,
+                    },
+                },
+            ],
+        });
+
         let planar_occluders_pipeline = TrianglesPipeline::new(&device);
         let planar_occluders_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
@@ -318,7 +363,11 @@ impl Application {
         });
         let planar_occluders_len;
         let planar_occluders = {
-            let planar_occluders = voxel_grid.get_planar_occluders(100000);
+            // Occluders stored by `occluder_builder` are used when the molecule has them
+            let planar_occluders: Vec<glm::Vec4> = match molecule.occluders() {
+                Some(occluders) => occluders.triangles.iter().map(|v| glm::vec4(v.x, v.y, v.z, 1.0)).collect(),
+                None => voxel_grid.get_planar_occluders(100000),
+            };
             println!("Ocluders: {}", planar_occluders.len() / 3);
             planar_occluders_len = planar_occluders.len();
             let mut res = Vec::new();
@@ -363,6 +412,11 @@ impl Application {
             grid,
             grid_bind_group,
 
+            mesh_pipeline,
+            surface_instance,
+            surface_bind_group,
+            surface_meshes,
+
             planar_occluders_pipeline,
             planar_occluders_bind_group,
             planar_occluders,
@@ -435,6 +489,17 @@ impl ApplicationSkeleton for Application {
                 rpass.set_bind_group(0, &self.grid_bind_group, &[]);
                 rpass.draw(0..36, 0..self.grid.count as u32);
             }
+
+            if self.options.render_surface {
+                rpass.set_pipeline(&self.mesh_pipeline.pipeline);
+                rpass.set_bind_group(0, &self.surface_bind_group, &[]);
+                for mesh in &self.surface_meshes {
+                    rpass.set_vertex_buffer(0, mesh.vertices(), 0, 0);
+                    rpass.set_vertex_buffer(1, mesh.normals(), 0, 0);
+                    rpass.set_index_buffer(mesh.indices(), 0, 0);
+                    rpass.draw_indexed(0..mesh.indices_len(), 0, 0..1);
+                }
+            }
         }
 
         if self.options.render_aabbs {
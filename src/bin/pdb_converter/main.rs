@@ -1,54 +1,354 @@
-use ron::ser::{to_string_pretty, PrettyConfig};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use wgpu_experiments::kmeans;
+use wgpu_experiments::lod;
 use wgpu_experiments::pdb_loader;
 use wgpu_experiments::rpdb;
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    let in_file_path: &str = &args[1];
-
-    let name = std::path::Path::new(in_file_path)
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .trim_end_matches(".pdb")
-        .to_ascii_uppercase();
-    let in_atoms = pdb_loader::load_molecule(std::path::Path::new(in_file_path));
+const USAGE: &str = "Usage: pdb_converter <input.pdb|.pqr|.cif|directory|glob>...
+       [-o output.ron|.rpdb] [--out-dir DIR] [--format ron|rpdb] [--force]
+       [--seed N] [--iterations N] [--tolerance X] [--bounding-sphere tight|minimal]
+       [--strategy kmeans|grid|agglomerative] [--ratio R]
+       [--metrics] [--metrics-json]";
+
+const STRATEGIES: [&str; 3] = ["kmeans", "grid", "agglomerative"];
+
+// Extensions picked up when a directory is given
+const INPUT_EXTENSIONS: [&str; 4] = ["pdb", "pqr", "cif", "mmcif"];
+
+struct Options {
+    inputs: Vec<String>,
+    /// Output file, only valid for a single input
+    output: Option<PathBuf>,
+    /// Directory of the outputs, next to the inputs if not given
+    out_dir: Option<PathBuf>,
+    /// Extension of the outputs, `ron` or `rpdb`
+    format: String,
+    /// Converts even the files whose output is up to date
+    force: bool,
+    kmeans: kmeans::KmeansOptions,
+    strategy: String,
+    /// Every LOD has `ratio` times fewer spheres than the previous one
+    ratio: f32,
+    metrics: bool,
+    /// Writes the metrics next to the output as `<output>.metrics.json`
+    metrics_json: bool,
+}
+
+// Bad arguments are not worth a backtrace
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    std::process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut out_dir = None;
+    let mut format = String::from("ron");
+    let mut force = false;
+    let mut kmeans = kmeans::KmeansOptions::default();
+    let mut strategy = String::from("kmeans");
+    let mut ratio = 4.0;
+    let mut metrics = false;
+    let mut metrics_json = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().unwrap_or_else(|| usage_error(&format!("Missing value for {}", name)));
+
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(value("--output"))),
+            "--out-dir" => out_dir = Some(PathBuf::from(value("--out-dir"))),
+            "--format" => format = value("--format"),
+            "--force" => force = true,
+            "--seed" => kmeans.seed = value("--seed").parse().unwrap_or_else(|_| usage_error("--seed expects an integer")),
+            "--iterations" => {
+                kmeans.max_iterations = value("--iterations")
+                    .parse()
+                    .unwrap_or_else(|_| usage_error("--iterations expects an integer"))
+            }
+            "--tolerance" => {
+                kmeans.tolerance = value("--tolerance")
+                    .parse()
+                    .unwrap_or_else(|_| usage_error("--tolerance expects a number"))
+            }
+            "--strategy" => strategy = value("--strategy"),
+            "--ratio" => ratio = value("--ratio").parse().unwrap_or_else(|_| usage_error("--ratio expects a number")),
+            "--metrics" => metrics = true,
+            "--metrics-json" => metrics_json = true,
+            "--bounding-sphere" => {
+                kmeans.bounding_sphere = match value("--bounding-sphere").as_str() {
+                    "tight" => kmeans::BoundingSphere::Tight,
+                    "minimal" => kmeans::BoundingSphere::Minimal,
+                    other => usage_error(&format!("Unknown bounding sphere {}", other)),
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    // Older invocations passed the output file as the second argument
+    if output.is_none() && positional.len() == 2 && is_output_path(Path::new(&positional[1])) {
+        output = positional.pop().map(PathBuf::from);
+    }
+
+    if positional.is_empty() {
+        usage_error("No input given");
+    }
+    if ratio <= 1.0 {
+        usage_error("--ratio must be greater than 1");
+    }
+    if format != "ron" && format != "rpdb" {
+        usage_error(&format!("Unknown format {}", format));
+    }
+    if !STRATEGIES.contains(&strategy.as_str()) {
+        usage_error(&format!("Unknown strategy {}", strategy));
+    }
+
+    Options {
+        inputs: positional,
+        output,
+        out_dir,
+        format,
+        force,
+        kmeans,
+        strategy,
+        ratio,
+        metrics,
+        metrics_json,
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+fn is_output_path(path: &Path) -> bool {
+    has_extension(path, &["ron", "rpdb"])
+}
+
+/// Expands directories (their molecule files) and glob patterns into the list of files to convert.
+fn collect_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        let path = Path::new(input);
+
+        if path.is_dir() {
+            let entries = std::fs::read_dir(path).map_err(|e| format!("{}: {}", input, e))?;
+            let mut directory_files: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && has_extension(path, &INPUT_EXTENSIONS))
+                .collect();
+            directory_files.sort();
+            files.extend(directory_files);
+        } else if path.exists() {
+            files.push(path.to_path_buf());
+        } else {
+            let paths = glob::glob(input).map_err(|e| format!("{}: {}", input, e))?;
+            let matches: Vec<PathBuf> = paths.filter_map(|path| path.ok()).filter(|path| path.is_file()).collect();
+            if matches.is_empty() {
+                return Err(format!("{}: no such file", input));
+            }
+            files.extend(matches);
+        }
+    }
+
+    // Directories and globs may overlap
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn molecule_name(input: &Path) -> String {
+    input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_ascii_uppercase())
+        .unwrap_or_default()
+}
+
+fn output_path(options: &Options, input: &Path) -> PathBuf {
+    if let Some(output) = &options.output {
+        return output.clone();
+    }
+
+    let directory = match &options.out_dir {
+        Some(out_dir) => out_dir.clone(),
+        None => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+
+    // Not `with_extension`, which would cut stems containing a dot
+    directory.join(format!("{}.{}", molecule_name(input), options.format))
+}
+
+// Output exists and is not older than its input
+fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+fn lod_builder(options: &Options) -> Box<dyn lod::LodBuilder> {
+    let bounding_sphere = options.kmeans.bounding_sphere;
+
+    match options.strategy.as_str() {
+        "kmeans" => Box::new(lod::KmeansBuilder { options: options.kmeans }),
+        "grid" => Box::new(lod::GridBuilder { bounding_sphere }),
+        "agglomerative" => Box::new(lod::AgglomerativeBuilder { bounding_sphere }),
+        other => panic!("Unknown strategy {}\n{}", other, USAGE),
+    }
+}
+
+/// Converts one file, returning the report printed once the file is done.
+fn convert(options: &Options, input: &Path, output: &Path) -> Result<String, String> {
+    let mut report = String::new();
+
+    let data = pdb_loader::try_load_molecule_data(input).map_err(|e| e.to_string())?;
+    if data.atoms.is_empty() {
+        return Err(format!("{}: no atoms", input.display()));
+    }
+    let in_atoms = data.atoms;
     let bounding_box = pdb_loader::bounding_box(&in_atoms);
 
-    let mut lods = vec![rpdb::MoleculeLod::new(in_atoms)];
-    loop {
-        let last_lod_index = lods.len() - 1;
-        let last_lod = &lods[last_lod_index];
+    let start = std::time::Instant::now();
+    let lods = lod::build_lods(lod_builder(options).as_ref(), in_atoms, options.ratio);
+    report += &format!(
+        "{} -> {}: {} LODs with {} in {:.2}s\n",
+        input.display(),
+        output.display(),
+        lods.len(),
+        options.strategy,
+        start.elapsed().as_secs_f32()
+    );
+    for (i, lod) in lods.iter().enumerate() {
+        report += &format!(
+            "  LOD {}: {} spheres, max radius {:.2}, error {:.2}\n",
+            i,
+            lod.atoms().len(),
+            lod.max_radius(),
+            lod.error().unwrap_or(0.0)
+        );
+    }
 
-        let new_centroids_num = last_lod.atoms().len() / 4;
+    if options.metrics || options.metrics_json {
+        let metrics = lod::metrics::lod_metrics(&lods, &lod::metrics::MetricsOptions::default());
 
-        if new_centroids_num <= 1 {
-            break;
+        report += &format!(
+            "{:>4} {:>9} {:>9} {:>12} {:>8} {:>10}\n",
+            "LOD", "spheres", "reduction", "volume", "volume %", "hausdorff"
+        );
+        for m in &metrics {
+            report += &format!(
+                "{:>4} {:>9} {:>9.1} {:>12.1} {:>8.1} {:>10.3}\n",
+                m.lod,
+                m.spheres,
+                m.reduction,
+                m.volume,
+                m.volume_ratio * 100.0,
+                m.hausdorff
+            );
         }
 
-        let new_atoms = kmeans::kmeans_spheres(last_lod.atoms(), new_centroids_num);
-        lods.push(rpdb::MoleculeLod::new(new_atoms));
+        if options.metrics_json {
+            let path = output.with_extension("metrics.json");
+            let json = serde_json::to_string_pretty(&metrics).map_err(|e| e.to_string())?;
+            std::fs::write(&path, json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
     }
 
     let molecule = rpdb::Molecule {
-        name: name.to_string(),
+        name: molecule_name(input),
         bounding_box,
         lods,
+        charges: data.charges,
+        attributes: data.attributes,
+        occluders: None,
     };
 
-    // Convert the molecule to a new RON format
-    let pretty = PrettyConfig::default();
-    let s = to_string_pretty(&molecule, pretty).expect("Serialization failed");
+    if let Some(directory) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(directory).map_err(|e| format!("{}: {}", directory.display(), e))?;
+    }
 
-    let out_file_path = if args.len() >= 3 {
-        args[2].clone()
-    } else {
-        std::path::Path::new(in_file_path).parent().unwrap().to_str().unwrap().to_owned() + "\\" + &name + ".ron"
-    };
-    println!("Writing to: {}", out_file_path);
+    // Binary container for `.rpdb` outputs, RON otherwise
+    molecule.try_save(output).map_err(|e| e.to_string())?;
+
+    Ok(report)
+}
+
+enum Outcome {
+    Converted,
+    Skipped,
+    Failed(String),
+}
+
+fn main() {
+    let options = parse_args();
+
+    let inputs = collect_inputs(&options.inputs).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if options.output.is_some() && inputs.len() != 1 {
+        usage_error("--output needs exactly one input, use --out-dir for several");
+    }
+
+    // Names are uppercased, so inputs differing only in case or extension would be written to the same file at once
+    let mut outputs: HashMap<PathBuf, &Path> = HashMap::new();
+    for input in &inputs {
+        if let Some(other) = outputs.insert(output_path(&options, input), input) {
+            usage_error(&format!(
+                "{} and {} would both be converted to {}",
+                other.display(),
+                input.display(),
+                output_path(&options, input).display()
+            ));
+        }
+    }
+
+    let outcomes: Vec<Outcome> = inputs
+        .par_iter()
+        .map(|input| {
+            let output = output_path(&options, input);
+            if !options.force && is_up_to_date(input, &output) {
+                println!("{} -> {}: up to date", input.display(), output.display());
+                return Outcome::Skipped;
+            }
 
-    std::fs::write(out_file_path, s).expect("Unable to write file");
+            match convert(&options, input, &output) {
+                Ok(report) => {
+                    print!("{}", report);
+                    Outcome::Converted
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    Outcome::Failed(e)
+                }
+            }
+        })
+        .collect();
+
+    let converted = outcomes.iter().filter(|o| matches!(o, Outcome::Converted)).count();
+    let skipped = outcomes.iter().filter(|o| matches!(o, Outcome::Skipped)).count();
+    let failed: Vec<&String> = outcomes
+        .iter()
+        .filter_map(|outcome| match outcome {
+            Outcome::Failed(e) => Some(e),
+            _ => None,
+        })
+        .collect();
+
+    println!("{} converted, {} up to date, {} failed", converted, skipped, failed.len());
+    if !failed.is_empty() {
+        for e in &failed {
+            eprintln!("  {}", e);
+        }
+        std::process::exit(1);
+    }
 }
@@ -1,56 +1,130 @@
-use glm::{quat, quat_to_mat4, translation, vec3};
-use nalgebra_glm as glm;
-use ron::ser::{to_string, PrettyConfig};
-use std::io::*;
+use std::path::Path;
+use wgpu_experiments::mesoscale;
 use wgpu_experiments::rpdb;
+use wgpu_experiments::rpdb::validate::{validate_structure, ValidationReport};
+
+const USAGE: &str = "Usage: structure_converter <input> <output> [--scale X] [--no-validate]
+       structure_converter validate <structure.txt|.ron|.rpdb> [--scale X]
+       Converts a mesoscale text structure to .ron/.rpdb, or a .ron/.rpdb structure back to text.
+       Converted structures are checked against the molecule files next to the output.";
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    std::process::exit(2);
+}
+
+fn is_rpdb_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| e.eq_ignore_ascii_case("ron") || e.eq_ignore_ascii_case("rpdb"))
+}
+
+fn load(path: &Path, scale: f32) -> rpdb::Structure {
+    let structure = if is_rpdb_path(path) {
+        rpdb::Structure::try_load(path)
+    } else {
+        mesoscale::load_structure(path, scale)
+    };
+
+    structure.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+fn print_report(report: &ValidationReport) {
+    if !report.structure_counts.is_empty() {
+        println!("Nested structures:");
+        for (name, count) in &report.structure_counts {
+            println!("  {}: {}", name, count);
+        }
+    }
+
+    println!("Instances:");
+    for (name, count) in &report.instance_counts {
+        println!("  {}: {}", name, count);
+    }
+
+    println!("Atoms per LOD:");
+    for (lod, count) in report.atom_counts.iter().enumerate() {
+        println!("  {}: {}", lod, count);
+    }
+
+    if let Some(bounding_box) = &report.bounding_box {
+        let (min, max) = (&bounding_box.min, &bounding_box.max);
+        println!(
+            "Bounding box: [{:.1}, {:.1}, {:.1}] - [{:.1}, {:.1}, {:.1}]",
+            min.x, min.y, min.z, max.x, max.y, max.z
+        );
+    }
+
+    if !report.is_valid() {
+        eprintln!("{} problems:", report.problems.len());
+        for problem in &report.problems {
+            eprintln!("  {}", problem);
+        }
+    }
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    let in_file_path: &str = &args[1];
-    let out_file_path: &str = &args[2];
-
-    let structure_file = std::fs::File::open(in_file_path).expect("Could not open structure file.");
-    let structure_reader = std::io::BufReader::new(&structure_file);
-    let mut molecule_names = Vec::new();
-    let mut molecule_model_matrices = Vec::new();
-    for line in structure_reader.lines() {
-        if let Ok(line) = line {
-            let parts: Vec<&str> = line.split(' ').collect();
-
-            if parts.len() == 9 {
-                let molecule_name = parts[0];
-
-                let molecule_position = vec3(
-                    parts[1].parse::<f32>().unwrap(),
-                    parts[2].parse::<f32>().unwrap(),
-                    parts[3].parse::<f32>().unwrap(),
-                );
-                let molecule_quaternion = quat(
-                    -parts[7].parse::<f32>().unwrap(),
-                    parts[4].parse::<f32>().unwrap(),
-                    parts[5].parse::<f32>().unwrap(),
-                    -parts[6].parse::<f32>().unwrap(),
-                );
-
-                molecule_names.push(molecule_name.to_string());
-
-                let translation = translation(&(3333.33 * molecule_position));
-                let rotation = quat_to_mat4(&molecule_quaternion);
-                let model_matrix = translation * rotation;
-
-                molecule_model_matrices.push(model_matrix);
+    let mut positional = Vec::new();
+    let mut scale = mesoscale::DEFAULT_SCALE;
+    let mut validate = true;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => {
+                scale = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| usage_error("--scale expects a number"))
             }
+            "--no-validate" => validate = false,
+            _ => positional.push(arg),
         }
     }
 
-    let structure = rpdb::Structure {
-        names: molecule_names,
-        model_matrices: molecule_model_matrices,
+    if positional.len() != 2 {
+        usage_error(&format!("Expected 2 arguments, got {}", positional.len()));
+    }
+
+    // Standalone validation
+    if positional[0] == "validate" {
+        let path = Path::new(&positional[1]);
+        let report = validate_structure(&load(path, scale), path);
+        print_report(&report);
+
+        std::process::exit(if report.is_valid() { 0 } else { 1 });
+    }
+
+    let in_file_path = Path::new(&positional[0]);
+    let out_file_path = Path::new(&positional[1]);
+
+    let structure = load(in_file_path, scale);
+    let result = if is_rpdb_path(in_file_path) && !is_rpdb_path(out_file_path) {
+        // The text format is flat, nested structures are resolved next to the input
+        rpdb::hierarchy::flatten(&structure, in_file_path).and_then(|resolved| {
+            mesoscale::save_structure(out_file_path, &resolved.structure, scale)?;
+            Ok(resolved.structure)
+        })
+    } else {
+        // Binary container for `.rpdb` outputs, RON otherwise
+        structure.try_save(out_file_path).map(|_| structure)
     };
 
-    // Convert the molecule to a new RON format
-    let pretty = PrettyConfig::default();
-    let s = to_string(&structure).expect("Serialization failed");
-    std::fs::write(out_file_path, s).expect("Unable to write file");
+    let written = result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    // The structure is written anyway, molecules might be converted only afterwards
+    if validate {
+        let report = validate_structure(&written, out_file_path);
+        print_report(&report);
+
+        if !report.is_valid() {
+            std::process::exit(1);
+        }
+    }
 }
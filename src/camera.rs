@@ -61,6 +61,11 @@ impl RotationCamera {
         camera
     }
 
+    /// Distance of the eye from the origin the camera rotates around.
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
     fn direction_vector(&self) -> glm::Vec3 {
         let yaw = self.yaw.to_radians();
         let pitch = self.pitch.to_radians();
@@ -0,0 +1,603 @@
+/*
+ * Based on the contour tracing library (Rust)
+ * https://github.com/STPR/contour_tracing
+ *
+ * Copyright (c) 2020, STPR - https://github.com/STPR
+ *
+ * SPDX-License-Identifier: EUPL-1.2
+ */
+
+//! Contours of 2D bitmasks traced with the Theo Pavlidis' algorithm (4-connected), and iso-contours of 2D
+//! scalar fields traced with marching squares.
+//!
+//! Polygons have their vertices on the pixel corners, pixel `(x, y)` spans `[x, x + 1] × [y, y + 1]`. With the
+//! y axis pointing down (as in images and SVG), outlines run clockwise and holes counterclockwise, so the signed
+//! areas of all polygons of a bitmask add up to the number of set pixels. Polygons are closed implicitly, the first
+//! vertex is not repeated at the end.
+use glm::{vec2, Vec2};
+use nalgebra_glm as glm;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+static T: [(i8, i8); 8] = [(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)];
+static O_VERTEX: [(i8, i8); 7] = [(-1, 0), (0, 0), (-1, -1), (0, 0), (0, -1), (0, 0), (0, 0)]; // Vertex coordinates for the outlines (bottom left) according to the orientation
+static H_VERTEX: [(i8, i8); 7] = [(0, 0), (0, 0), (-1, 0), (0, 0), (-1, -1), (0, 0), (0, -1)]; // Vertex coordinates for the holes (bottom right) according to the orientation
+static O_VALUE: [i8; 7] = [1, 0, 2, 0, 4, 0, 8]; // Value to add into the array of contours for the outlines
+static H_VALUE: [i8; 7] = [-4, 0, -8, 0, -1, 0, -2]; // Value to add into the array of contours for the holes
+
+// Walk along the outlines or the holes
+struct Walk {
+    outline: bool,
+    o: [usize; 8],
+    rot: i8,
+    viv: (usize, usize, usize),
+    c_vertex: [(i8, i8); 7],
+    c_value: [i8; 7],
+}
+
+static OUTLINE: Walk = Walk {
+    outline: true,
+    o: [2, 3, 4, 5, 6, 7, 0, 1],
+    rot: 2,
+    viv: (7, 1, 0),
+    c_vertex: O_VERTEX,
+    c_value: O_VALUE,
+};
+static HOLE: Walk = Walk {
+    outline: false,
+    o: [4, 5, 6, 7, 0, 1, 2, 3],
+    rot: -2,
+    viv: (1, 7, 6),
+    c_vertex: H_VERTEX,
+    c_value: H_VALUE,
+};
+
+// Array of contours (the bitmask with a border, marked where contours were traced) and the polygons traced so far
+struct Tracer<'a> {
+    contours: &'a mut [Vec<i8>],
+    polygons: Vec<Polygon>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PolygonKind {
+    /// Boundary of a set region, clockwise.
+    Outline,
+    /// Boundary of an unset region inside an outline, counterclockwise.
+    Hole,
+}
+
+#[derive(Clone, Debug)]
+pub struct Polygon {
+    pub kind: PolygonKind,
+    pub points: Vec<Vec2>,
+}
+
+impl Polygon {
+    /// Area with the y axis pointing down, positive for outlines and negative for holes.
+    pub fn signed_area(&self) -> f32 {
+        let n = self.points.len();
+        if n < 3 {
+            return 0.0;
+        }
+
+        // Relative to the first vertex, small polygons far from the origin keep their precision
+        let origin = self.points[0];
+        let twice_area: f32 = (1..n - 1)
+            .map(|i| {
+                let (a, b) = (self.points[i] - origin, self.points[i + 1] - origin);
+                a.x * b.y - b.x * a.y
+            })
+            .sum();
+
+        twice_area / 2.0
+    }
+
+    /// Polygon without the vertices closer than `tolerance` to the simplified outline (Douglas-Peucker).
+    pub fn simplified(&self, tolerance: f32) -> Polygon {
+        Polygon {
+            kind: self.kind,
+            points: simplify_closed(&self.points, tolerance),
+        }
+    }
+}
+
+/// Traces the contours of a `width` × `height` bitmask, `is_set(x, y)` tells whether a pixel is set. Polygons are
+/// simplified if a tolerance (in pixels) is given.
+pub fn trace_contours<F: Fn(usize, usize) -> bool>(width: usize, height: usize, is_set: F, tolerance: Option<f32>) -> Vec<Polygon> {
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+
+    let rows: usize = height;
+    let cols: usize = width;
+
+    let mut contours = vec![vec![0i8; cols + 2]; rows + 2]; // The array of contours needs a border of 1 bit
+    for y in 0..rows {
+        for x in 0..cols {
+            contours[y + 1][x + 1] = is_set(x, y) as i8;
+        }
+    }
+
+    let mut tracer = Tracer {
+        contours: &mut contours,
+        polygons: Vec::new(),
+    };
+    let mut ol: usize;
+    let mut hl: usize;
+    for y in 1..=rows {
+        ol = 1;
+        hl = 1;
+        for x in 1..=cols {
+            let contours = &tracer.contours;
+            if ol == hl && contours[y][x] == 1 && contours[y][x - 1] <= 0 && contours[y - 1][x] <= 0 {
+                tracer.trace(x, y, &OUTLINE);
+            }
+            match tracer.contours[y][x] {
+                2 | 4 | 10 | 12 => ol += 1,
+                5 | 7 | 13 | 15 => ol -= 1,
+                _ => {}
+            }
+            let contours = &tracer.contours;
+            if ol > hl && contours[y][x] == 0 && contours[y][x - 1] > 0 && contours[y - 1][x] > 0 {
+                tracer.trace(x, y, &HOLE);
+            }
+            match tracer.contours[y][x] {
+                -1 | -3 | -9 | -11 => hl += 1,
+                -4 | -6 | -12 | -14 => hl -= 1,
+                _ => {}
+            }
+        }
+    }
+    let polygons = tracer.polygons;
+
+    match tolerance {
+        Some(tolerance) => polygons.iter().map(|polygon| polygon.simplified(tolerance)).collect(),
+        None => polygons,
+    }
+}
+
+// Vertex reached by a horizontal or vertical step of the contour, in pixel corner coordinates
+fn step(vertex: &mut (usize, usize), cx: usize, cy: usize, orientation: usize, c_vertex: &[(i8, i8); 7]) {
+    // Contour array coordinates have a border of 1, the vertex offsets are -1 or 0
+    if orientation == 0 || orientation == 4 {
+        vertex.0 = cx.wrapping_add(c_vertex[orientation].0 as usize);
+    } else {
+        vertex.1 = cy.wrapping_add(c_vertex[orientation].1 as usize);
+    }
+}
+
+impl Tracer<'_> {
+    fn trace(&mut self, x: usize, y: usize, walk: &Walk) {
+        let Walk {
+            outline,
+            mut o,
+            rot,
+            viv,
+            c_vertex,
+            c_value,
+        } = *walk;
+        let contours = &mut *self.contours;
+
+        let mut cx = x; // Current x
+        let mut cy = y; // Current y
+        let mut v: usize = 1; // Number of vertices
+
+        let mut vertex = (
+            cx.wrapping_add(c_vertex[o[0]].0 as usize),
+            cy.wrapping_add(c_vertex[o[0]].1 as usize),
+        );
+        let mut vertices = vec![vertex];
+        let mut push = |vertex: (usize, usize)| {
+            if vertices.last() != Some(&vertex) {
+                vertices.push(vertex);
+            }
+        };
+
+        let mut rn: u8;
+        loop {
+            let neighbors: [i8; 8] = [
+                contours[cy - 1][cx],
+                contours[cy - 1][cx + 1],
+                contours[cy][cx + 1],
+                contours[cy + 1][cx + 1],
+                contours[cy + 1][cx],
+                contours[cy + 1][cx - 1],
+                contours[cy][cx - 1],
+                contours[cy - 1][cx - 1],
+            ];
+            if outline {
+                if neighbors[o[7]] > 0 && neighbors[o[0]] > 0 {
+                    rn = 1;
+                } else if neighbors[o[0]] > 0 {
+                    rn = 2;
+                } else if neighbors[o[1]] > 0 && neighbors[o[2]] > 0 {
+                    rn = 3;
+                } else {
+                    rn = 0;
+                }
+            } else {
+                if neighbors[o[1]] <= 0 && neighbors[o[0]] <= 0 {
+                    rn = 1;
+                } else if neighbors[o[0]] <= 0 {
+                    rn = 2;
+                } else if neighbors[o[7]] <= 0 && neighbors[o[6]] <= 0 {
+                    rn = 3;
+                } else {
+                    rn = 0;
+                }
+            }
+            if rn == 1 {
+                contours[cy][cx] += c_value[o[0]];
+                cx = cx.wrapping_add(T[o[viv.0]].0 as usize);
+                cy = cy.wrapping_add(T[o[viv.0]].1 as usize);
+                o.rotate_right(rot.rem_euclid(8) as usize); // Rotate 90 degrees, counterclockwise for the outlines (rot = 2) or clockwise for the holes (rot = -2)
+                v += 1;
+                step(&mut vertex, cx, cy, o[0], &c_vertex);
+                push(vertex);
+            } else if rn == 2 {
+                contours[cy][cx] += c_value[o[0]];
+                cx = cx.wrapping_add(T[o[0]].0 as usize);
+                cy = cy.wrapping_add(T[o[0]].1 as usize);
+            } else if rn == 3 {
+                contours[cy][cx] += c_value[o[0]];
+                o.rotate_left(rot.rem_euclid(8) as usize); // Rotate 90 degrees, clockwise for the outlines (rot = 2) or counterclockwise for the holes (rot = -2)
+                contours[cy][cx] += c_value[o[0]];
+                v += 1;
+                step(&mut vertex, cx, cy, o[0], &c_vertex);
+                push(vertex);
+                o.rotate_right(rot.rem_euclid(8) as usize);
+                cx = cx.wrapping_add(T[o[viv.1]].0 as usize);
+                cy = cy.wrapping_add(T[o[viv.1]].1 as usize);
+                v += 1;
+                step(&mut vertex, cx, cy, o[0], &c_vertex);
+                push(vertex);
+            } else {
+                contours[cy][cx] += c_value[o[0]];
+                o.rotate_left(rot.rem_euclid(8) as usize);
+                v += 1;
+                step(&mut vertex, cx, cy, o[0], &c_vertex);
+                push(vertex);
+            }
+            if cx == x && cy == y && v > 2 {
+                break;
+            }
+        }
+        loop {
+            contours[cy][cx] += c_value[o[0]];
+            if o[0] == viv.2 {
+                break;
+            }
+            o.rotate_left(rot.rem_euclid(8) as usize);
+            step(&mut vertex, cx, cy, o[0], &c_vertex);
+            push(vertex);
+        }
+
+        // The contour ends where it started
+        if vertices.len() > 1 && vertices.first() == vertices.last() {
+            vertices.pop();
+        }
+
+        self.polygons.push(Polygon {
+            kind: if outline { PolygonKind::Outline } else { PolygonKind::Hole },
+            points: vertices.iter().map(|&(x, y)| vec2(x as f32, y as f32)).collect(),
+        });
+    }
+}
+
+// Crossing of the iso-line on the edge from sample `a` to sample `b`
+fn edge_crossing(a: Vec2, b: Vec2, value_a: f32, value_b: f32, iso: f32) -> Vec2 {
+    // Samples outside the field are infinite, the crossing is then halfway
+    let t = if value_a.is_finite() && value_b.is_finite() {
+        ((iso - value_a) / (value_b - value_a)).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+
+    a + (b - a) * t
+}
+
+/// Traces the iso-contours of a `width` × `height` scalar field with marching squares. Pixels where
+/// `value(x, y) < iso` are inside, so signed distances can be used directly and coverages negated. Everything
+/// outside the field is outside. Values are sampled at the pixel centers and the iso-line is interpolated between
+/// them, so contours follow the field below the pixel size. Polygons follow the same conventions as
+/// `trace_contours` and are simplified if a tolerance (in pixels) is given.
+pub fn iso_contours<F: Fn(usize, usize) -> f32>(width: usize, height: usize, value: F, iso: f32, tolerance: Option<f32>) -> Vec<Polygon> {
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+
+    // Samples with a border of 1 outside the field
+    let (cols, rows) = (width + 2, height + 2);
+    let mut samples = vec![std::f32::INFINITY; cols * rows];
+    for y in 0..height {
+        for x in 0..width {
+            samples[(y + 1) * cols + x + 1] = value(x, y);
+        }
+    }
+    let inside = |x: usize, y: usize| samples[y * cols + x] < iso;
+    let position = |x: usize, y: usize| vec2(x as f32 - 0.5, y as f32 - 0.5);
+
+    // Every segment starts at the edge where its contour leaves the inside of a cell, keyed by that edge.
+    // Horizontal edges have even keys, vertical edges odd ones.
+    let mut segments: BTreeMap<usize, (usize, Vec2)> = BTreeMap::new();
+    for y in 0..rows - 1 {
+        for x in 0..cols - 1 {
+            // Corners and edges clockwise from the top left, the y axis points down
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let edges = [
+                2 * (y * cols + x),
+                2 * (y * cols + x + 1) + 1,
+                2 * ((y + 1) * cols + x),
+                2 * (y * cols + x) + 1,
+            ];
+
+            // Crossings clockwise, `true` where the walk leaves the inside
+            let mut crossings = Vec::with_capacity(4);
+            for i in 0..4 {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                if inside(a.0, a.1) != inside(b.0, b.1) {
+                    let point = edge_crossing(
+                        position(a.0, a.1),
+                        position(b.0, b.1),
+                        samples[a.1 * cols + a.0],
+                        samples[b.1 * cols + b.0],
+                        iso,
+                    );
+                    crossings.push((edges[i], inside(a.0, a.1), point));
+                }
+            }
+            if crossings.is_empty() {
+                continue;
+            }
+
+            // Exits are joined to the next entry clockwise, which keeps the inside on the right. Saddles with
+            // an inside center join the inside corners, so exits take the previous entry instead.
+            let center: f32 = corners.iter().map(|c| samples[c.1 * cols + c.0]).sum::<f32>() / 4.0;
+            let connect_inside = crossings.len() == 2 || center < iso;
+            let n = crossings.len();
+            for i in 0..n {
+                let (edge, exit, point) = crossings[i];
+                if exit {
+                    let next = if connect_inside { (i + 1) % n } else { (i + n - 1) % n };
+                    segments.insert(edge, (crossings[next].0, point));
+                }
+            }
+        }
+    }
+
+    // Segments are chained into closed polygons
+    let mut polygons = Vec::new();
+    while let Some(&start) = segments.keys().next() {
+        let mut points = Vec::new();
+        let mut edge = start;
+        while let Some((next, point)) = segments.remove(&edge) {
+            // Samples equal to `iso` put several crossings on the same point
+            if points.last() != Some(&point) {
+                points.push(point);
+            }
+            edge = next;
+        }
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+
+        let mut polygon = Polygon {
+            kind: PolygonKind::Outline,
+            points,
+        };
+        let area = polygon.signed_area();
+        if area < 0.0 {
+            polygon.kind = PolygonKind::Hole;
+        }
+        if area != 0.0 {
+            polygons.push(polygon);
+        }
+    }
+
+    match tolerance {
+        Some(tolerance) => polygons.iter().map(|polygon| polygon.simplified(tolerance)).collect(),
+        None => polygons,
+    }
+}
+
+fn line_point_distance(l1: &Vec2, l2: &Vec2, p: &Vec2) -> f32 {
+    let length = glm::distance(l1, l2);
+    if length == 0.0 {
+        return glm::distance(l1, p);
+    }
+
+    ((l2.y - l1.y) * p.x - (l2.x - l1.x) * p.y + l2.x * l1.y - l2.y * l1.x).abs() / length
+}
+
+/// Douglas-Peucker simplification of an open polyline, the end points are kept.
+pub fn simplify(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let last = points.len() - 1;
+    let (index, distance) = (1..last)
+        .map(|i| (i, line_point_distance(&points[0], &points[last], &points[i])))
+        .fold((0, 0.0), |max, d| if d.1 > max.1 { d } else { max });
+
+    if distance > tolerance {
+        let mut result = simplify(&points[..=index], tolerance);
+        result.pop();
+        result.extend(simplify(&points[index..], tolerance));
+        result
+    } else {
+        vec![points[0], points[last]]
+    }
+}
+
+/// Douglas-Peucker simplification of a closed polygon. The polygon is split at the vertex farthest from the first
+/// one, both halves are simplified as polylines.
+pub fn simplify_closed(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    if points.len() < 4 {
+        return points.to_vec();
+    }
+
+    let (farthest, _) = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (i, glm::distance(&points[0], p)))
+        .fold((0, 0.0), |max, d| if d.1 > max.1 { d } else { max });
+
+    let mut second_half = points[farthest..].to_vec();
+    second_half.push(points[0]);
+
+    let mut result = simplify(&points[..=farthest], tolerance);
+    result.pop();
+    result.extend(simplify(&second_half, tolerance));
+    result.pop();
+    result
+}
+
+/// SVG path data of polygons, every polygon is a closed subpath.
+pub fn svg_path_data(polygons: &[Polygon]) -> String {
+    let mut data = String::new();
+    for polygon in polygons {
+        for (i, point) in polygon.points.iter().enumerate() {
+            data += &format!("{}{} {}", if i == 0 { "M" } else { "L" }, point.x, point.y);
+        }
+        data += "Z";
+    }
+
+    data
+}
+
+/// Writes polygons traced from a `width` × `height` bitmask as an SVG document.
+pub fn write_svg<W: Write>(mut writer: W, width: usize, height: usize, polygons: &[Polygon]) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+        width, height, width, height
+    )?;
+    writeln!(writer, "<path fill-rule=\"evenodd\" d=\"{}\"/>", svg_path_data(polygons))?;
+    writeln!(writer, "</svg>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_mask(width: usize, height: usize, density: f64, seed: u64) -> Vec<bool> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..width * height).map(|_| rng.gen_bool(density)).collect()
+    }
+
+    // Boundary edges of the set pixels, between a set pixel and an unset one or the outside of the mask
+    fn boundary_len(mask: &[bool], width: usize, height: usize) -> usize {
+        let is_set =
+            |x: isize, y: isize| x >= 0 && y >= 0 && x < width as isize && y < height as isize && mask[y as usize * width + x as usize];
+        let mut len = 0;
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                if is_set(x, y) {
+                    len += [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                        .iter()
+                        .filter(|(dx, dy)| !is_set(x + dx, y + dy))
+                        .count();
+                }
+            }
+        }
+
+        len
+    }
+
+    #[test]
+    fn random_masks_trace_closed_contours() {
+        for seed in 0..50 {
+            let (width, height) = (1 + seed as usize % 23, 1 + seed as usize * 7 % 19);
+            let mask = random_mask(width, height, 0.2 + (seed % 7) as f64 * 0.1, seed);
+            let polygons = trace_contours(width, height, |x, y| mask[y * width + x], None);
+
+            let mut perimeter = 0.0;
+            for polygon in &polygons {
+                let n = polygon.points.len();
+                assert!(n >= 4, "seed {}: {:?}", seed, polygon);
+                assert_ne!(polygon.points[0], polygon.points[n - 1]);
+
+                // Every edge, the closing one included, is a horizontal or vertical step along the pixel corners
+                for i in 0..n {
+                    let (a, b) = (polygon.points[i], polygon.points[(i + 1) % n]);
+                    assert!(a.x >= 0.0 && a.y >= 0.0 && a.x <= width as f32 && a.y <= height as f32);
+                    assert!((a.x == b.x) != (a.y == b.y), "seed {}: edge {:?} {:?}", seed, a, b);
+                    perimeter += glm::distance(&a, &b);
+                }
+            }
+
+            // The contours follow every boundary edge exactly once
+            assert_eq!(perimeter as usize, boundary_len(&mask, width, height), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn outlines_and_holes_have_opposite_windings() {
+        let mut holes = 0;
+        for seed in 0..50 {
+            let (width, height) = (16 + seed as usize % 17, 16 + seed as usize % 13);
+            let mask = random_mask(width, height, 0.3 + (seed % 5) as f64 * 0.1, seed + 100);
+            let polygons = trace_contours(width, height, |x, y| mask[y * width + x], None);
+
+            for polygon in &polygons {
+                match polygon.kind {
+                    PolygonKind::Outline => assert!(polygon.signed_area() > 0.0, "seed {}: {:?}", seed, polygon),
+                    PolygonKind::Hole => {
+                        assert!(polygon.signed_area() < 0.0, "seed {}: {:?}", seed, polygon);
+                        holes += 1;
+                    }
+                }
+            }
+
+            let area: f32 = polygons.iter().map(Polygon::signed_area).sum();
+            let set = mask.iter().filter(|set| **set).count();
+            assert_eq!(area as usize, set, "seed {}", seed);
+        }
+        assert!(holes > 0);
+    }
+
+    // Signed distance to a ring centered in a 64 × 64 field, sampled at the pixel centers
+    fn ring_distance(x: usize, y: usize, outer: f32, inner: f32) -> f32 {
+        let distance = glm::distance(&vec2(x as f32 + 0.5, y as f32 + 0.5), &vec2(32.0, 32.0));
+        (distance - outer).max(inner - distance)
+    }
+
+    #[test]
+    fn iso_contours_of_a_disc() {
+        let polygons = iso_contours(64, 64, |x, y| ring_distance(x, y, 20.0, std::f32::NEG_INFINITY), 0.0, None);
+        assert_eq!(polygons.len(), 1);
+
+        let disc = &polygons[0];
+        let n = disc.points.len();
+        assert_eq!(disc.kind, PolygonKind::Outline);
+        assert!(n > 100);
+        for i in 0..n {
+            let point = disc.points[i];
+            assert_ne!(point, disc.points[(i + 1) % n]);
+            assert!((glm::distance(&point, &vec2(32.0, 32.0)) - 20.0).abs() < 0.1, "{:?}", point);
+        }
+
+        let area = std::f32::consts::PI * 20.0 * 20.0;
+        assert!(
+            (disc.signed_area() / area - 1.0).abs() < 0.01,
+            "{} traced, {} exact",
+            disc.signed_area(),
+            area
+        );
+    }
+
+    #[test]
+    fn iso_contours_of_a_ring() {
+        let polygons = iso_contours(64, 64, |x, y| ring_distance(x, y, 20.0, 8.0), 0.0, None);
+        assert_eq!(polygons.len(), 2);
+
+        let outline = polygons.iter().find(|polygon| polygon.kind == PolygonKind::Outline).unwrap();
+        let hole = polygons.iter().find(|polygon| polygon.kind == PolygonKind::Hole).unwrap();
+        let (outer, inner) = (std::f32::consts::PI * 20.0 * 20.0, std::f32::consts::PI * 8.0 * 8.0);
+        assert!((outline.signed_area() / outer - 1.0).abs() < 0.01);
+        assert!((-hole.signed_area() / inner - 1.0).abs() < 0.02);
+    }
+}
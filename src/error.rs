@@ -0,0 +1,66 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Errors produced by the fallible loaders of the library.
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be opened, read or written.
+    Io { path: PathBuf, source: std::io::Error },
+    /// A line of a text format could not be parsed. Lines are numbered from 1.
+    Parse { path: PathBuf, line: usize, message: String },
+    /// The file was read but its content is not valid (RON, OBJ or binary container).
+    Format { path: PathBuf, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn parse<M: Into<String>>(path: &Path, line: usize, message: M) -> Self {
+        Error::Parse {
+            path: path.to_path_buf(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub fn format<M: Into<String>>(path: &Path, message: M) -> Self {
+        Error::Format {
+            path: path.to_path_buf(),
+            message: message.into(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Error::Io { path, .. } => path,
+            Error::Parse { path, .. } => path,
+            Error::Format { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            Error::Format { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,69 +1,361 @@
-use glm::{distance, distance2, vec4, zero, Vec3, Vec4};
+use crate::spatial::{minimal_enclosing_sphere, morton_code, tight_enclosing_sphere, KdTree};
+use glm::{distance, vec4, zero, Vec3, Vec4};
 use nalgebra_glm as glm;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+
+/// How the sphere of a cluster is fitted around its member spheres.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BoundingSphere {
+    /// Centered at the cluster centroid with the radius reaching the farthest member sphere.
+    Tight,
+    /// Approximately minimal sphere enclosing the member spheres.
+    Minimal,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct KmeansOptions {
+    /// Upper bound on the number of assignment/update rounds.
+    pub max_iterations: usize,
+    /// Clustering stops once no centroid moves further than this (in the units of the input).
+    pub tolerance: f32,
+    /// Seed of the k-means++ initialization, equal seeds give identical clusterings.
+    pub seed: u64,
+    /// Fitting of the output spheres, both variants fully enclose the member spheres.
+    pub bounding_sphere: BoundingSphere,
+}
+
+impl Default for KmeansOptions {
+    fn default() -> Self {
+        Self {
+            max_iterations: 20,
+            tolerance: 1.0e-3,
+            seed: 0,
+            bounding_sphere: BoundingSphere::Minimal,
+        }
+    }
+}
 
 pub fn kmeans_spheres(points: &[Vec4], centroids_num: usize) -> Vec<Vec4> {
-    use rand::prelude::*;
-    let mut rng = rand::thread_rng();
+    kmeans_spheres_with_memberships(points, centroids_num, &KmeansOptions::default()).0
+}
 
-    let mut centroids: Vec<Vec4> = Vec::new();
-    let mut memberships: Vec<i32> = vec![0; points.len()];
+// Squared distances of the points to their closest centroid, with the sum and the maximum of every subtree of a
+// complete binary tree over them, so that k-means++ samples a point in logarithmic time
+struct DistanceTree {
+    leaves: usize,
+    sums: Vec<f64>,
+    maxima: Vec<f32>,
+    // Leaves set since the last update of their ancestors
+    changed: Vec<usize>,
+}
 
-    // init
-    let centroid_step = (points.len() as f32 / centroids_num as f32).ceil() as usize;
-    for i in 0..centroids_num {
-        if i * centroid_step < points.len() {
-            centroids.push(points[i * centroid_step]);
-        } else {
-            centroids.push(points[(rng.gen::<f32>() * points.len() as f32).floor() as usize]);
+impl DistanceTree {
+    fn new(len: usize, value: f32) -> Self {
+        let leaves = len.next_power_of_two();
+        let mut tree = Self {
+            leaves,
+            sums: vec![0.0; 2 * leaves],
+            maxima: vec![0.0; 2 * leaves],
+            changed: Vec::new(),
+        };
+        for leaf in 0..len {
+            tree.sums[leaves + leaf] = value as f64;
+            tree.maxima[leaves + leaf] = value;
+        }
+        for node in (1..leaves).rev() {
+            tree.update_node(node);
         }
+
+        tree
     }
 
-    for _ in 0..5 {
-        // Find centroids
-        for (point_index, point) in points.iter().enumerate() {
-            let mut min_dist = std::f32::INFINITY;
-            let mut closest_centroid = -1;
-            for (centroid_index, centroid) in centroids.iter().enumerate() {
-                let dist = distance2(&point, &centroid);
-
-                if dist < min_dist {
-                    min_dist = dist;
-                    closest_centroid = centroid_index as i32;
-                }
+    fn get(&self, leaf: usize) -> f32 {
+        self.maxima[self.leaves + leaf]
+    }
+
+    // The sums and maxima above the leaf are stale until `update`
+    fn set(&mut self, leaf: usize, value: f32) {
+        let node = self.leaves + leaf;
+        self.sums[node] = value as f64;
+        self.maxima[node] = value;
+        self.changed.push(node);
+    }
+
+    // Parents are recomputed from their children rather than shifted by the difference, so the sums do not drift
+    fn update_node(&mut self, node: usize) {
+        self.sums[node] = self.sums[2 * node] + self.sums[2 * node + 1];
+        self.maxima[node] = self.maxima[2 * node].max(self.maxima[2 * node + 1]);
+    }
+
+    // Updates every ancestor of the changed leaves once, level by level
+    fn update(&mut self) {
+        let mut nodes = std::mem::take(&mut self.changed);
+        nodes.sort_unstable();
+        while !nodes.is_empty() && nodes[0] > 1 {
+            for node in nodes.iter_mut() {
+                *node /= 2;
+            }
+            nodes.dedup();
+            for &node in &nodes {
+                self.update_node(node);
             }
+        }
+        nodes.clear();
+        self.changed = nodes;
+    }
+
+    fn sum(&self) -> f64 {
+        self.sums[1]
+    }
+
+    fn max(&self) -> f32 {
+        self.maxima[1]
+    }
+
+    // Leaf where the running sum of the distances passes `target`
+    fn find(&self, mut target: f64) -> usize {
+        let mut node = 1;
+        while node < self.leaves {
+            let left = 2 * node;
+            if target < self.sums[left] {
+                node = left;
+            } else {
+                target -= self.sums[left];
+                node = left + 1;
+            }
+        }
+
+        node - self.leaves
+    }
+}
+
+// k-means++: every next centroid is picked with probability proportional to the squared distance to the closest one
+// picked so far. A new centroid can only get closer to the points within the largest of these distances, which a
+// k-d tree of the points finds without visiting the others. Points are ordered along a Morton curve, so the ones
+// updated together are close in memory and share most of their ancestors in the distance tree.
+fn kmeans_plus_plus(points: &[Vec4], centroids_num: usize, seed: u64) -> Vec<Vec4> {
+    let mut centroids = Vec::with_capacity(centroids_num);
+    if centroids_num == 0 || points.is_empty() {
+        return centroids;
+    }
+
+    let mut min = points[0].xyz();
+    let mut max = points[0].xyz();
+    for point in points {
+        min = glm::min2(&min, &point.xyz());
+        max = glm::max2(&max, &point.xyz());
+    }
+    let mut ordered: Vec<(u32, usize)> = points
+        .par_iter()
+        .enumerate()
+        .map(|(i, p)| (morton_code(&p.xyz(), &min, &max), i))
+        .collect();
+    ordered.par_sort_unstable();
+    let ordered: Vec<Vec4> = ordered.into_iter().map(|(_, i)| points[i]).collect();
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let tree = KdTree::new(ordered.iter().map(|p| p.xyz()).collect());
+    let mut min_distances = DistanceTree::new(ordered.len(), std::f32::INFINITY);
 
-            memberships[point_index] = closest_centroid;
+    let mut next = rng.gen_range(0, ordered.len());
+    loop {
+        let centroid = ordered[next];
+        centroids.push(centroid);
+        if centroids.len() >= centroids_num {
+            break;
         }
 
+        let radius = min_distances.max().sqrt();
+        tree.for_each_within(&centroid.xyz(), radius, |i, distance| {
+            if distance < min_distances.get(i) {
+                min_distances.set(i, distance);
+            }
+        });
+        min_distances.update();
+
+        // All the remaining points coincide with a centroid
+        let sum = min_distances.sum();
+        next = if sum <= 0.0 {
+            rng.gen_range(0, ordered.len())
+        } else {
+            min_distances.find(rng.gen::<f64>() * sum).min(ordered.len() - 1)
+        };
+    }
+
+    centroids
+}
+
+/// Clusters the spheres like `kmeans_spheres` and also returns, for every input point, the index of its output sphere.
+///
+/// Nearest centroids are found through a k-d tree in parallel, centroids are then updated in a single
+/// serial pass so the result does not depend on the number of threads.
+pub fn kmeans_spheres_with_memberships(points: &[Vec4], centroids_num: usize, options: &KmeansOptions) -> (Vec<Vec4>, Vec<u32>) {
+    // Memberships double as the starting guess of the nearest-centroid search
+    let mut memberships: Vec<u32> = vec![std::u32::MAX; points.len()];
+    if points.is_empty() {
+        return (Vec::new(), memberships);
+    }
+
+    // init
+    let mut centroids: Vec<Vec4> = kmeans_plus_plus(points, centroids_num.min(points.len()).max(1), options.seed);
+
+    for _ in 0..options.max_iterations.max(1) {
+        // Find centroids
+        let tree = KdTree::new(centroids.iter().map(|c| c.xyz()).collect());
+        points
+            .par_iter()
+            .zip(memberships.par_iter_mut())
+            .for_each(|(point, membership)| *membership = tree.nearest_from(&point.xyz(), *membership as usize).unwrap().0 as u32);
+
         // Update centroids
+        let mut sums: Vec<Vec3> = vec![zero(); centroids.len()];
+        let mut member_counts = vec![0u32; centroids.len()];
+        for (point, &membership) in points.iter().zip(memberships.iter()) {
+            let id = membership as usize;
+
+            sums[id] += point.xyz();
+            member_counts[id] += 1;
+        }
+
+        let mut max_shift = 0.0f32;
         for id in 0..centroids.len() {
-            let mut member_count = 0;
-            let mut bounding_radius = 0.0f32;
-
-            let mut new_centroid: Vec3 = zero();
-            let old_centroid: Vec3 = centroids[id].xyz();
-
-            for i in 0..points.len() {
-                let add_centroid: bool = memberships[i] == id as i32;
-                let inc_point: Vec3 = if add_centroid { points[i].xyz() } else { zero() };
-
-                new_centroid += inc_point;
-                member_count += if add_centroid { 1 } else { 0 };
-                bounding_radius = if add_centroid {
-                    if distance(&old_centroid, &inc_point) > bounding_radius {
-                        distance(&old_centroid, &inc_point)
-                    } else {
-                        bounding_radius
-                    }
-                } else {
-                    bounding_radius
-                };
+            // Empty clusters keep their position and get dropped at the end
+            if member_counts[id] == 0 {
+                continue;
             }
 
-            new_centroid = new_centroid * (1.0 / member_count as f32);
-            centroids[id] = vec4(new_centroid[0], new_centroid[1], new_centroid[2], bounding_radius);
+            let new_centroid = sums[id] / member_counts[id] as f32;
+            max_shift = max_shift.max(distance(&centroids[id].xyz(), &new_centroid));
+            centroids[id] = vec4(new_centroid[0], new_centroid[1], new_centroid[2], 0.0);
+        }
+
+        if max_shift <= options.tolerance {
+            break;
+        }
+    }
+
+    fit_spheres(points, &memberships, centroids.len(), options.bounding_sphere)
+}
+
+/// Fits an enclosing sphere around the member spheres of every cluster, `memberships[i]` being the cluster of `points[i]`.
+///
+/// Empty clusters are dropped, the returned memberships are renumbered accordingly.
+pub fn fit_spheres(points: &[Vec4], memberships: &[u32], clusters_num: usize, bounding_sphere: BoundingSphere) -> (Vec<Vec4>, Vec<u32>) {
+    // Group the member spheres of every cluster
+    let mut members_offsets = vec![0usize; clusters_num + 1];
+    for &membership in memberships {
+        members_offsets[membership as usize + 1] += 1;
+    }
+    for i in 1..members_offsets.len() {
+        members_offsets[i] += members_offsets[i - 1];
+    }
+    let mut fill = members_offsets.clone();
+    let mut members = vec![zero(); points.len()];
+    for (point, &membership) in points.iter().zip(memberships.iter()) {
+        members[fill[membership as usize]] = *point;
+        fill[membership as usize] += 1;
+    }
+
+    // Fit the output spheres around their members, dropping empty clusters and renumbering the memberships accordingly
+    let mut remap = vec![0u32; clusters_num];
+    let mut spheres = Vec::new();
+    for id in 0..clusters_num {
+        let cluster = &members[members_offsets[id]..members_offsets[id + 1]];
+        if !cluster.is_empty() {
+            remap[id] = spheres.len() as u32;
+            spheres.push(match bounding_sphere {
+                BoundingSphere::Tight => tight_enclosing_sphere(cluster),
+                BoundingSphere::Minimal => minimal_enclosing_sphere(cluster),
+            });
         }
     }
 
-    centroids.into_iter().filter(|v| v[3] > 0.0).collect()
+    let memberships = memberships.iter().map(|m| remap[*m as usize]).collect();
+
+    (spheres, memberships)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_spheres(len: usize, seed: u64) -> Vec<Vec4> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..len)
+            .map(|_| {
+                vec4(
+                    rng.gen_range(-20.0, 20.0),
+                    rng.gen_range(-20.0, 20.0),
+                    rng.gen_range(-20.0, 20.0),
+                    rng.gen_range(0.5, 2.0),
+                )
+            })
+            .collect()
+    }
+
+    fn assert_members_enclosed(points: &[Vec4], spheres: &[Vec4], memberships: &[u32]) {
+        assert_eq!(points.len(), memberships.len());
+        for (point, &membership) in points.iter().zip(memberships.iter()) {
+            let sphere = spheres[membership as usize];
+            let reach = distance(&sphere.xyz(), &point.xyz()) + point.w;
+            assert!(reach <= sphere.w * (1.0 + 1.0e-5), "{:?} sticks out of {:?}", point, sphere);
+        }
+    }
+
+    #[test]
+    fn lod_spheres_enclose_their_members() {
+        let points = random_spheres(2000, 1);
+        for &bounding_sphere in &[BoundingSphere::Tight, BoundingSphere::Minimal] {
+            let options = KmeansOptions {
+                bounding_sphere,
+                ..Default::default()
+            };
+            let (spheres, memberships) = kmeans_spheres_with_memberships(&points, 100, &options);
+
+            assert!(!spheres.is_empty() && spheres.len() <= 100);
+            assert_members_enclosed(&points, &spheres, &memberships);
+        }
+    }
+
+    #[test]
+    fn fit_spheres_drops_empty_clusters() {
+        let points = random_spheres(50, 2);
+        let memberships: Vec<u32> = (0..points.len() as u32).map(|i| (i % 5) * 2).collect();
+        let (spheres, memberships) = fit_spheres(&points, &memberships, 10, BoundingSphere::Minimal);
+
+        assert_eq!(spheres.len(), 5);
+        assert_members_enclosed(&points, &spheres, &memberships);
+    }
+
+    #[test]
+    fn distance_tree_samples_by_weight() {
+        let mut tree = DistanceTree::new(5, 1.0);
+        tree.set(1, 0.0);
+        tree.set(3, 4.0);
+        tree.update();
+
+        assert_eq!((tree.sum(), tree.max()), (7.0, 4.0));
+        let picks: Vec<usize> = [0.0, 0.5, 1.0, 2.5, 5.9, 6.0, 6.9]
+            .iter()
+            .map(|&target| tree.find(target))
+            .collect();
+        assert_eq!(picks, vec![0, 0, 2, 3, 3, 4, 4]);
+    }
+
+    #[test]
+    fn seeding_is_reproducible_on_large_inputs() {
+        let points = random_spheres(5000, 3);
+        let centroids = kmeans_plus_plus(&points, 500, 7);
+
+        assert_eq!(centroids, kmeans_plus_plus(&points, 500, 7));
+        assert_ne!(centroids, kmeans_plus_plus(&points, 500, 8));
+
+        // Centroids are distinct input points
+        let mut indices: Vec<usize> = centroids.iter().map(|c| points.iter().position(|p| p == c).unwrap()).collect();
+        indices.sort_unstable();
+        indices.dedup();
+        assert_eq!(indices.len(), 500);
+    }
 }
@@ -1,8 +1,15 @@
 pub mod camera;
+pub mod contour;
+pub mod error;
 pub mod kmeans;
+pub mod lod;
+pub mod mesoscale;
 pub mod pdb_loader;
 pub mod pipelines;
 pub mod rpdb;
+pub mod scene;
+pub mod spatial;
+pub mod voxel;
 
 use bytemuck::*;
 use obj::*;
@@ -187,39 +194,52 @@ impl Mesh {
         self.indices_len
     }
 
-    pub fn from_obj<P: AsRef<Path>>(device: &wgpu::Device, path: P, scale: f32) -> Self {
-        let file = std::io::BufReader::new(std::fs::File::open(path).unwrap());
-        let obj: Obj = load_obj(file).expect("Incorrect .obj file");
+    /// Uploads a mesh, `vertices` and `normals` hold three floats per vertex.
+    pub fn new(device: &wgpu::Device, vertices: &[f32], normals: &[f32], indices: &[u16]) -> Self {
+        assert_eq!(vertices.len(), normals.len());
 
-        let mut vertices_cpu = Vec::new();
-        for v in obj.vertices.iter() {
-            vertices_cpu.push(v.position[0] * scale);
-            vertices_cpu.push(v.position[1] * scale);
-            vertices_cpu.push(v.position[2] * scale);
-        }
-        let vertices_len = vertices_cpu.len() as u32;
-        let vertices = device.create_buffer_with_data(cast_slice(&vertices_cpu), wgpu::BufferUsage::VERTEX);
+        Self {
+            vertices: device.create_buffer_with_data(cast_slice(vertices), wgpu::BufferUsage::VERTEX),
+            vertices_len: vertices.len() as u32,
 
-        let mut normals = Vec::new();
-        for v in obj.vertices.iter() {
-            normals.push(v.normal[0]);
-            normals.push(v.normal[1]);
-            normals.push(v.normal[2]);
+            normals: device.create_buffer_with_data(cast_slice(normals), wgpu::BufferUsage::VERTEX),
+
+            indices: device.create_buffer_with_data(cast_slice(indices), wgpu::BufferUsage::INDEX),
+            indices_len: indices.len() as u32,
         }
-        let normals = device.create_buffer_with_data(cast_slice(&normals), wgpu::BufferUsage::VERTEX);
+    }
 
-        let indices = obj.indices;
-        let indices_len = indices.len() as u32;
-        let indices = device.create_buffer_with_data(cast_slice(&indices), wgpu::BufferUsage::INDEX);
+    /// Uploads the surface of a voxel grid, split into as many meshes as the 16-bit indices need.
+    pub fn from_surface_mesh(device: &wgpu::Device, surface: &voxel::surface::SurfaceMesh) -> Vec<Self> {
+        surface
+            .split(std::u16::MAX as usize + 1)
+            .iter()
+            .map(|part| {
+                let vertices: Vec<f32> = part.vertices.iter().flat_map(|v| v.iter().copied()).collect();
+                let normals: Vec<f32> = part.normals.iter().flat_map(|n| n.iter().copied()).collect();
+                let indices: Vec<u16> = part.indices.iter().map(|&i| i as u16).collect();
+
+                Self::new(device, &vertices, &normals, &indices)
+            })
+            .collect()
+    }
 
-        Self {
-            vertices,
-            vertices_len,
+    pub fn from_obj<P: AsRef<Path>>(device: &wgpu::Device, path: P, scale: f32) -> Self {
+        Self::try_from_obj(device, path, scale).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-            normals,
+    pub fn try_from_obj<P: AsRef<Path>>(device: &wgpu::Device, path: P, scale: f32) -> error::Result<Self> {
+        let path = path.as_ref();
+        let file = std::io::BufReader::new(std::fs::File::open(path).map_err(|e| error::Error::io(path, e))?);
+        let obj: Obj = load_obj(file).map_err(|e| error::Error::format(path, format!("Incorrect .obj file: {:?}", e)))?;
 
-            indices,
-            indices_len,
+        let mut vertices = Vec::new();
+        let mut normals = Vec::new();
+        for v in obj.vertices.iter() {
+            vertices.extend(v.position.iter().map(|p| p * scale));
+            normals.extend_from_slice(&v.normal);
         }
+
+        Ok(Self::new(device, &vertices, &normals, &obj.indices))
     }
 }
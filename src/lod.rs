@@ -0,0 +1,264 @@
+use crate::kmeans::{fit_spheres, kmeans_spheres_with_memberships, BoundingSphere, KmeansOptions};
+use crate::rpdb::MoleculeLod;
+use crate::spatial::KdTree;
+use glm::{Vec3, Vec4};
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+
+pub mod metrics;
+
+/// Strategy that reduces one level of detail into a coarser one.
+pub trait LodBuilder: Sync {
+    /// Clusters the `xyzr` spheres into about `target` enclosing spheres (never more).
+    ///
+    /// Returns the new spheres and, for every input sphere, the index of the output sphere containing it.
+    fn reduce(&self, spheres: &[Vec4], target: usize) -> (Vec<Vec4>, Vec<u32>);
+}
+
+/// Builds the whole LOD chain: the atoms first, then every next level has `ratio` times fewer spheres
+/// until a single one would remain. Levels after the first remember their source atoms.
+pub fn build_lods(builder: &dyn LodBuilder, atoms: Vec<Vec4>, ratio: f32) -> Vec<MoleculeLod> {
+    assert!(ratio > 1.0, "LOD reduction ratio has to be greater than 1");
+
+    // Sphere of every source atom in the last LOD
+    let mut atom_clusters: Vec<u32> = (0..atoms.len() as u32).collect();
+
+    let mut first_lod = MoleculeLod::new(atoms);
+    first_lod.set_error(0.0);
+
+    let mut lods = vec![first_lod];
+    loop {
+        let last_lod = &lods[lods.len() - 1];
+
+        let target = (last_lod.atoms().len() as f32 / ratio) as usize;
+        if target <= 1 {
+            break;
+        }
+
+        let (new_atoms, memberships) = builder.reduce(last_lod.atoms(), target);
+        // Strategy could not reduce any further
+        if new_atoms.len() >= last_lod.atoms().len() {
+            break;
+        }
+
+        for cluster in atom_clusters.iter_mut() {
+            *cluster = memberships[*cluster as usize];
+        }
+
+        let error = geometric_error(lods[0].atoms(), &new_atoms, &atom_clusters);
+        let mut lod = MoleculeLod::with_members(new_atoms, &atom_clusters);
+        lod.set_error(error);
+        lods.push(lod);
+    }
+
+    lods
+}
+
+/// Upper bound on how far the surface of the spheres strays from the atoms, `clusters[i]` being the sphere of atom `i`.
+///
+/// Every point of a sphere is at most `r + |c - c_a| - r_a` away from the surface of any of its member atoms `a`.
+pub fn geometric_error(atoms: &[Vec4], spheres: &[Vec4], clusters: &[u32]) -> f32 {
+    let mut closest = vec![std::f32::INFINITY; spheres.len()];
+    for (atom, &cluster) in atoms.iter().zip(clusters.iter()) {
+        let sphere = &spheres[cluster as usize];
+        closest[cluster as usize] = closest[cluster as usize].min(glm::distance(&sphere.xyz(), &atom.xyz()) - atom.w);
+    }
+
+    spheres
+        .iter()
+        .zip(closest.iter())
+        .filter(|(_, closest)| closest.is_finite())
+        .fold(0.0f32, |error, (sphere, closest)| error.max(sphere.w + closest))
+}
+
+/// Size in pixels of a world-space `error` seen from `distance` through a camera with vertical `fov` (radians)
+/// and a viewport `viewport_height` pixels tall.
+pub fn screen_space_error(error: f32, distance: f32, fov: f32, viewport_height: f32) -> f32 {
+    if distance <= 0.0 {
+        return std::f32::INFINITY;
+    }
+
+    error * viewport_height / (2.0 * distance * (fov * 0.5).tan())
+}
+
+/// Coarsest LOD whose error stays below `max_pixel_error` on screen, LOD 0 if none does.
+///
+/// `distance` is measured from the camera to the center of the molecule. The error is projected from the closest
+/// point of the bounding sphere (`bounding_radius`) but never from closer than the `near` plane, so a camera at the
+/// surface of a large molecule still gets a fine LOD.
+pub fn select_lod(
+    lods: &[MoleculeLod],
+    distance: f32,
+    bounding_radius: f32,
+    near: f32,
+    fov: f32,
+    viewport_height: f32,
+    max_pixel_error: f32,
+) -> usize {
+    let distance = (distance - bounding_radius).max(near);
+
+    (1..lods.len())
+        .rev()
+        .find(|&i| {
+            lods[i].error().map_or(false, |error| {
+                screen_space_error(error, distance, fov, viewport_height) <= max_pixel_error
+            })
+        })
+        .unwrap_or(0)
+}
+
+/// Lloyd's k-means with k-means++ seeding, see `kmeans_spheres_with_memberships`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct KmeansBuilder {
+    pub options: KmeansOptions,
+}
+
+impl LodBuilder for KmeansBuilder {
+    fn reduce(&self, spheres: &[Vec4], target: usize) -> (Vec<Vec4>, Vec<u32>) {
+        kmeans_spheres_with_memberships(spheres, target, &self.options)
+    }
+}
+
+/// Voxel-grid clustering: spheres whose centers fall into the same cell are merged.
+///
+/// The cell size is searched for so that the number of occupied cells is as close to the target as possible without exceeding it.
+#[derive(Copy, Clone, Debug)]
+pub struct GridBuilder {
+    pub bounding_sphere: BoundingSphere,
+}
+
+impl Default for GridBuilder {
+    fn default() -> Self {
+        Self {
+            bounding_sphere: BoundingSphere::Minimal,
+        }
+    }
+}
+
+// Cell key of every point, 21 bits per axis
+fn grid_keys(points: &[Vec4], min: &Vec3, cell_size: f32) -> Vec<u64> {
+    points
+        .par_iter()
+        .map(|p| {
+            let cell = (p.xyz() - min) / cell_size;
+            let x = (cell.x as u64).min(0x1F_FFFF);
+            let y = (cell.y as u64).min(0x1F_FFFF);
+            let z = (cell.z as u64).min(0x1F_FFFF);
+            (x << 42) | (y << 21) | z
+        })
+        .collect()
+}
+
+fn occupied_cells(keys: &[u64]) -> Vec<u64> {
+    let mut cells = keys.to_vec();
+    cells.par_sort_unstable();
+    cells.dedup();
+    cells
+}
+
+impl LodBuilder for GridBuilder {
+    fn reduce(&self, spheres: &[Vec4], target: usize) -> (Vec<Vec4>, Vec<u32>) {
+        const SEARCH_STEPS: usize = 24;
+
+        if spheres.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+
+        let mut min = spheres[0].xyz();
+        let mut max = spheres[0].xyz();
+        for sphere in spheres {
+            min = glm::min2(&min, &sphere.xyz());
+            max = glm::max2(&max, &sphere.xyz());
+        }
+        let extent = glm::comp_max(&(max - min)).max(std::f32::EPSILON);
+
+        // A cell as big as the whole box gives a single cluster, bisect towards the target from there
+        let mut coarse = extent * 1.001;
+        let mut fine = 0.0f32;
+        for _ in 0..SEARCH_STEPS {
+            let cell_size = 0.5 * (coarse + fine);
+            if occupied_cells(&grid_keys(spheres, &min, cell_size)).len() <= target.max(1) {
+                coarse = cell_size;
+            } else {
+                fine = cell_size;
+            }
+        }
+
+        let keys = grid_keys(spheres, &min, coarse);
+        let cells = occupied_cells(&keys);
+        let memberships: Vec<u32> = keys.iter().map(|key| cells.binary_search(key).unwrap() as u32).collect();
+
+        fit_spheres(spheres, &memberships, cells.len(), self.bounding_sphere)
+    }
+}
+
+/// Bottom-up agglomerative clustering with centroid linkage.
+///
+/// Every round merges the mutually nearest pairs of clusters (closest pairs first) until the target is reached.
+#[derive(Copy, Clone, Debug)]
+pub struct AgglomerativeBuilder {
+    pub bounding_sphere: BoundingSphere,
+}
+
+impl Default for AgglomerativeBuilder {
+    fn default() -> Self {
+        Self {
+            bounding_sphere: BoundingSphere::Minimal,
+        }
+    }
+}
+
+impl LodBuilder for AgglomerativeBuilder {
+    fn reduce(&self, spheres: &[Vec4], target: usize) -> (Vec<Vec4>, Vec<u32>) {
+        let target = target.max(1);
+
+        // Centroid and member count of every current cluster
+        let mut centroids: Vec<Vec3> = spheres.iter().map(|s| s.xyz()).collect();
+        let mut weights: Vec<u32> = vec![1; spheres.len()];
+        let mut memberships: Vec<u32> = (0..spheres.len() as u32).collect();
+
+        while centroids.len() > target {
+            let tree = KdTree::new(centroids.clone());
+            let nearest: Vec<(usize, f32)> = (0..centroids.len())
+                .into_par_iter()
+                .map(|i| tree.nearest_where(&centroids[i], |j| j != i).unwrap())
+                .collect();
+
+            let mut pairs: Vec<(f32, usize, usize)> = nearest
+                .iter()
+                .enumerate()
+                .filter(|&(i, &(j, _))| i < j && nearest[j].0 == i)
+                .map(|(i, &(j, dist))| (dist, i, j))
+                .collect();
+            pairs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            pairs.truncate(centroids.len() - target);
+
+            let mut remap = vec![std::u32::MAX; centroids.len()];
+            let mut new_centroids = Vec::with_capacity(centroids.len() - pairs.len());
+            let mut new_weights = Vec::with_capacity(centroids.len() - pairs.len());
+            for &(_, i, j) in &pairs {
+                remap[i] = new_centroids.len() as u32;
+                remap[j] = new_centroids.len() as u32;
+
+                let weight = weights[i] + weights[j];
+                new_centroids.push((centroids[i] * weights[i] as f32 + centroids[j] * weights[j] as f32) / weight as f32);
+                new_weights.push(weight);
+            }
+            for i in 0..centroids.len() {
+                if remap[i] == std::u32::MAX {
+                    remap[i] = new_centroids.len() as u32;
+                    new_centroids.push(centroids[i]);
+                    new_weights.push(weights[i]);
+                }
+            }
+
+            for membership in memberships.iter_mut() {
+                *membership = remap[*membership as usize];
+            }
+            centroids = new_centroids;
+            weights = new_weights;
+        }
+
+        fit_spheres(spheres, &memberships, centroids.len(), self.bounding_sphere)
+    }
+}
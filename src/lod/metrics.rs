@@ -0,0 +1,183 @@
+//! Fidelity of a LOD chain measured against its first level (the atoms).
+//!
+//! Volumes are estimated on a regular sample grid. Surfaces are sampled on every sphere and only the samples
+//! not buried in another sphere are kept, distances between surfaces are measured between these samples
+//! and so are accurate up to the sample spacing.
+use crate::rpdb::MoleculeLod;
+use crate::spatial::KdTree;
+use glm::{Vec3, Vec4};
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug)]
+pub struct MetricsOptions {
+    /// Approximate distance between surface samples (in the units of the molecule, Å).
+    pub surface_spacing: f32,
+    /// Number of grid samples used to estimate the volume of one LOD.
+    pub volume_samples: usize,
+}
+
+impl Default for MetricsOptions {
+    fn default() -> Self {
+        Self {
+            surface_spacing: 1.0,
+            volume_samples: 1 << 21,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LodMetrics {
+    pub lod: usize,
+    pub spheres: usize,
+    /// Number of atoms of LOD 0 per sphere of this LOD.
+    pub reduction: f32,
+    /// Volume covered by the union of the spheres.
+    pub volume: f32,
+    /// Volume relative to LOD 0.
+    pub volume_ratio: f32,
+    /// Symmetric Hausdorff distance between the surface of this LOD and the surface of LOD 0.
+    pub hausdorff: f32,
+}
+
+/// Union of spheres with containment queries.
+struct SphereUnion<'a> {
+    spheres: &'a [Vec4],
+    tree: KdTree,
+    max_radius: f32,
+}
+
+impl<'a> SphereUnion<'a> {
+    fn new(spheres: &'a [Vec4]) -> Self {
+        Self {
+            spheres,
+            tree: KdTree::new(spheres.iter().map(|s| s.xyz()).collect()),
+            max_radius: spheres.iter().fold(0.0f32, |max, s| max.max(s.w)),
+        }
+    }
+
+    /// True if the point lies inside a sphere, shrunk by `margin`.
+    fn contains(&self, point: &Vec3, margin: f32) -> bool {
+        let mut inside = false;
+        self.tree.for_each_within(point, self.max_radius, |i, dist2| {
+            let radius = self.spheres[i].w - margin;
+            inside |= radius > 0.0 && dist2 < radius * radius;
+        });
+
+        inside
+    }
+
+    /// Points on the spheres that are not inside any other sphere.
+    fn surface(&self, spacing: f32) -> Vec<Vec3> {
+        const GOLDEN_ANGLE: f32 = 2.399_963;
+        // Large spheres of coarse LODs are sampled more sparsely, their error is far above the spacing anyway
+        const MAX_SPHERE_SAMPLES: usize = 1024;
+
+        self.spheres
+            .par_iter()
+            .flat_map_iter(|sphere| {
+                let area = 4.0 * std::f32::consts::PI * sphere.w * sphere.w;
+                let count = ((area / (spacing * spacing)).ceil() as usize).max(4).min(MAX_SPHERE_SAMPLES);
+
+                // Fibonacci lattice
+                (0..count)
+                    .map(move |k| {
+                        let y = 1.0 - 2.0 * (k as f32 + 0.5) / count as f32;
+                        let r = (1.0 - y * y).max(0.0).sqrt();
+                        let phi = k as f32 * GOLDEN_ANGLE;
+                        sphere.xyz() + glm::vec3(phi.cos() * r, y, phi.sin() * r) * sphere.w
+                    })
+                    .filter(move |p| !self.contains(p, 1.0e-3 * sphere.w.max(1.0)))
+            })
+            .collect()
+    }
+
+    fn volume(&self, samples: usize) -> f32 {
+        if self.spheres.is_empty() {
+            return 0.0;
+        }
+
+        let mut min = self.spheres[0].xyz();
+        let mut max = self.spheres[0].xyz();
+        for sphere in self.spheres {
+            min = glm::min2(&min, &(sphere.xyz() - glm::vec3(sphere.w, sphere.w, sphere.w)));
+            max = glm::max2(&max, &(sphere.xyz() + glm::vec3(sphere.w, sphere.w, sphere.w)));
+        }
+
+        let extent = max - min;
+        let step = (extent.x * extent.y * extent.z / samples.max(1) as f32)
+            .cbrt()
+            .max(std::f32::EPSILON);
+        let resolution = [
+            (extent.x / step).ceil() as usize,
+            (extent.y / step).ceil() as usize,
+            (extent.z / step).ceil() as usize,
+        ];
+
+        let inside: usize = (0..resolution[2])
+            .into_par_iter()
+            .map(|z| {
+                let mut inside = 0;
+                for y in 0..resolution[1] {
+                    for x in 0..resolution[0] {
+                        let point = min + glm::vec3(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) * step;
+                        if self.contains(&point, 0.0) {
+                            inside += 1;
+                        }
+                    }
+                }
+                inside
+            })
+            .sum();
+
+        inside as f32 * step * step * step
+    }
+}
+
+/// Largest distance from the samples to the closest sample of the other surface.
+fn directed_hausdorff(samples: &[Vec3], target: &KdTree) -> f32 {
+    samples
+        .par_iter()
+        .map(|p| target.nearest(p).map_or(0.0, |(_, dist2)| dist2.sqrt()))
+        .reduce(|| 0.0f32, f32::max)
+}
+
+/// Measures every LOD against the first one.
+pub fn lod_metrics(lods: &[MoleculeLod], options: &MetricsOptions) -> Vec<LodMetrics> {
+    if lods.is_empty() {
+        return Vec::new();
+    }
+
+    let reference = SphereUnion::new(lods[0].atoms());
+    let reference_surface = KdTree::new(reference.surface(options.surface_spacing));
+    let reference_volume = reference.volume(options.volume_samples);
+
+    lods.iter()
+        .enumerate()
+        .map(|(lod, level)| {
+            let union = SphereUnion::new(level.atoms());
+            let volume = if lod == 0 {
+                reference_volume
+            } else {
+                union.volume(options.volume_samples)
+            };
+
+            let hausdorff = if lod == 0 {
+                0.0
+            } else {
+                let surface = KdTree::new(union.surface(options.surface_spacing));
+                directed_hausdorff(surface.points(), &reference_surface).max(directed_hausdorff(reference_surface.points(), &surface))
+            };
+
+            LodMetrics {
+                lod,
+                spheres: level.atoms().len(),
+                reduction: lods[0].atoms().len() as f32 / level.atoms().len().max(1) as f32,
+                volume,
+                volume_ratio: volume / reference_volume.max(std::f32::EPSILON),
+                hausdorff,
+            }
+        })
+        .collect()
+}
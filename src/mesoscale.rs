@@ -0,0 +1,142 @@
+//! Mesoscale structure text format: one molecule instance per line,
+//! `<model-name> x y z rot.y rot.z -rot.s -rot.x <sequence-id>`.
+//!
+//! Positions are stored in units of `scale` Å, rotations as a quaternion with the components reordered
+//! and partly negated as above. The sequence ID is the order in which the instance was populated.
+use crate::error::{Error, Result};
+use crate::rpdb;
+use glm::{quat, quat_to_mat4, translation, Mat4, Quat, Vec3};
+use nalgebra_glm as glm;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Scale of the positions in the SARS-CoV-2 mesoscale models.
+pub const DEFAULT_SCALE: f32 = 3333.33;
+
+/// One line of a structure file.
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub name: String,
+    /// Position in the units of the file, see `model_matrix`.
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub sequence_id: u64,
+}
+
+impl Instance {
+    pub fn model_matrix(&self, scale: f32) -> Mat4 {
+        translation(&(scale * self.position)) * quat_to_mat4(&self.rotation)
+    }
+
+    /// Decomposes a rigid model matrix back into an instance.
+    pub fn from_model_matrix(name: &str, model_matrix: &Mat4, scale: f32, sequence_id: u64) -> Self {
+        let position = model_matrix.column(3).xyz() / scale;
+        let rotation = glm::to_quat(model_matrix);
+
+        Self {
+            name: name.to_string(),
+            position,
+            rotation,
+            sequence_id,
+        }
+    }
+}
+
+/// Parses a single line. Empty lines give `None`.
+pub fn parse_instance(path: &Path, line_number: usize, line: &str) -> Result<Option<Instance>> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    if parts.len() != 9 {
+        return Err(Error::parse(
+            path,
+            line_number,
+            format!(
+                "expected 9 fields (name x y z rot.y rot.z -rot.s -rot.x sequence-id), found {}",
+                parts.len()
+            ),
+        ));
+    }
+
+    let number = |i: usize| {
+        parts[i]
+            .parse::<f32>()
+            .map_err(|_| Error::parse(path, line_number, format!("invalid number '{}'", parts[i])))
+    };
+    let position = glm::vec3(number(1)?, number(2)?, number(3)?);
+    let rotation = quat(-number(7)?, number(4)?, number(5)?, -number(6)?);
+    let sequence_id = parts[8]
+        .parse::<u64>()
+        .map_err(|_| Error::parse(path, line_number, format!("invalid sequence id '{}'", parts[8])))?;
+
+    Ok(Some(Instance {
+        name: parts[0].to_string(),
+        position,
+        rotation,
+        sequence_id,
+    }))
+}
+
+pub fn read_instances(path: &Path) -> Result<Vec<Instance>> {
+    let file = std::fs::File::open(path).map_err(|e| Error::io(path, e))?;
+
+    let mut instances = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| Error::io(path, e))?;
+        if let Some(instance) = parse_instance(path, index + 1, &line)? {
+            instances.push(instance);
+        }
+    }
+
+    Ok(instances)
+}
+
+pub fn write_instances(path: &Path, instances: &[Instance]) -> Result<()> {
+    let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+    let mut writer = BufWriter::new(file);
+
+    for instance in instances {
+        let (p, r) = (&instance.position, &instance.rotation.coords);
+        writeln!(
+            writer,
+            "{} {} {} {} {} {} {} {} {}",
+            instance.name, p.x, p.y, p.z, r.y, r.z, -r.w, -r.x, instance.sequence_id
+        )
+        .map_err(|e| Error::io(path, e))?;
+    }
+
+    writer.flush().map_err(|e| Error::io(path, e))
+}
+
+/// Reads a structure file, positions are multiplied by `scale`.
+pub fn load_structure(path: &Path, scale: f32) -> Result<rpdb::Structure> {
+    let instances = read_instances(path)?;
+
+    Ok(rpdb::Structure {
+        names: instances.iter().map(|i| i.name.clone()).collect(),
+        model_matrices: instances.iter().map(|i| i.model_matrix(scale)).collect(),
+        sequence_ids: instances.iter().map(|i| i.sequence_id).collect(),
+        structure_names: Vec::new(),
+        structure_model_matrices: Vec::new(),
+    })
+}
+
+/// Writes a structure in the text format, positions are divided by `scale`. The format has no nested structures,
+/// resolve them first (see `rpdb::hierarchy`).
+///
+/// Instances without a stored sequence ID are numbered in order.
+pub fn save_structure(path: &Path, structure: &rpdb::Structure, scale: f32) -> Result<()> {
+    let instances: Vec<Instance> = structure
+        .names
+        .iter()
+        .zip(structure.model_matrices.iter())
+        .enumerate()
+        .map(|(i, (name, model_matrix))| {
+            let sequence_id = structure.sequence_ids.get(i).copied().unwrap_or(i as u64);
+            Instance::from_model_matrix(name, model_matrix, scale, sequence_id)
+        })
+        .collect();
+
+    write_instances(path, &instances)
+}
@@ -1,6 +1,7 @@
+use crate::error::{Error, Result};
+use crate::mesoscale;
 use crate::rpdb;
 use glm::*;
-use lib3dmol::structures::{atom::AtomType, GetAtom};
 use nalgebra_glm as glm;
 use std::collections::HashMap;
 use std::fs;
@@ -44,83 +45,382 @@ pub fn center_atoms(mut atoms: Vec<Vec4>) -> Vec<Vec4> {
     atoms
 }
 
+// Parses a number of a text record, reporting the file and line on failure
+fn parse_number<T: std::str::FromStr>(path: &Path, line: usize, value: &str, what: &str) -> Result<T> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| Error::parse(path, line, format!("invalid {} '{}'", what, value.trim())))
+}
+
+fn open_lines(path: &Path) -> Result<impl Iterator<Item = (usize, std::io::Result<String>)>> {
+    let file = File::open(path).map_err(|e| Error::io(path, e))?;
+
+    Ok(BufReader::new(file).lines().enumerate().map(|(i, line)| (i + 1, line)))
+}
+
+/// Loads a PDB file, assigning radii by element.
 pub fn load_molecule(path: &Path) -> Vec<Vec4> {
+    try_load_molecule(path).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_load_molecule(path: &Path) -> Result<Vec<Vec4>> {
+    load_pdb_data(path).map(|data| data.atoms)
+}
+
+/// Atoms of a single molecule together with everything the source file knew about them.
+///
+/// `charges` and `attributes` are either empty or have one entry per atom.
+pub struct MoleculeData {
+    pub atoms: Vec<Vec4>,
+    pub charges: Vec<f32>,
+    pub attributes: Vec<rpdb::AtomAttributes>,
+}
+
+/// Loads a PDB, PQR or mmCIF file, choosing the reader from the file extension.
+pub fn load_molecule_data(path: &Path) -> MoleculeData {
+    try_load_molecule_data(path).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_load_molecule_data(path: &Path) -> Result<MoleculeData> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+
+    match extension.as_str() {
+        "pqr" => load_pqr_data(path),
+        "cif" | "mmcif" => load_cif_data(path),
+        _ => load_pdb_data(path),
+    }
+}
+
+// Guesses the element from a PDB atom name when the element column is missing
+fn element_from_atom_name(atom_name: &str) -> String {
+    atom_name
+        .trim()
+        .chars()
+        .find(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase().to_string())
+        .unwrap_or_default()
+}
+
+// Fixed-column slice of a PDB record, tolerant to short lines
+fn pdb_column(line: &str, start: usize, end: usize) -> &str {
+    let end = end.min(line.len());
+    if start >= end {
+        return "";
+    }
+
+    line.get(start..end).unwrap_or("").trim()
+}
+
+fn load_pdb_data(path: &Path) -> Result<MoleculeData> {
     let mut atoms = Vec::new();
+    let mut attributes = Vec::new();
+
+    for (line_number, line) in open_lines(path)? {
+        let line = line.map_err(|e| Error::io(path, e))?;
+        if line.starts_with("ENDMDL") {
+            break;
+        }
+        if !line.starts_with("ATOM") && !line.starts_with("HETATM") {
+            continue;
+        }
 
-    let molecule_structure = lib3dmol::parser::read_pdb(path.to_str().unwrap(), "");
-    for atom in molecule_structure.get_atom() {
-        let radius = match atom.a_type {
-            AtomType::Carbon => 1.548,
-            AtomType::Hydrogen => 1.100,
-            AtomType::Nitrogen => 1.400,
-            AtomType::Oxygen => 1.348,
-            AtomType::Phosphorus => 1.880,
-            AtomType::Sulfur => 1.880,
-            _ => 1.0, // 'A': 1.5
+        let x = parse_number::<f32>(path, line_number, pdb_column(&line, 30, 38), "x coordinate")?;
+        let y = parse_number::<f32>(path, line_number, pdb_column(&line, 38, 46), "y coordinate")?;
+        let z = parse_number::<f32>(path, line_number, pdb_column(&line, 46, 54), "z coordinate")?;
+
+        let element = match pdb_column(&line, 76, 78) {
+            "" => element_from_atom_name(pdb_column(&line, 12, 16)),
+            element => element.to_ascii_uppercase(),
         };
-        atoms.push(glm::vec4(atom.coord[0], atom.coord[1], atom.coord[2], radius));
+
+        atoms.push(glm::vec4(x, y, z, element_radius(&element)));
+        attributes.push(rpdb::AtomAttributes {
+            element,
+            chain_id: pdb_column(&line, 21, 22).to_string(),
+            residue_name: pdb_column(&line, 17, 20).to_string(),
+            residue_number: pdb_column(&line, 22, 26).parse::<i32>().unwrap_or(0),
+            b_factor: pdb_column(&line, 60, 66).parse::<f32>().unwrap_or(0.0),
+            occupancy: pdb_column(&line, 54, 60).parse::<f32>().unwrap_or(1.0),
+        });
     }
 
-    center_atoms(atoms)
+    Ok(MoleculeData {
+        atoms: center_atoms(atoms),
+        charges: Vec::new(),
+        attributes,
+    })
 }
 
-pub fn load_molecules(path: &Path) -> Vec<Vec4> {
+/// Loads a PQR file (PDB2PQR output), keeping the radius and partial charge of every atom.
+///
+/// PQR records are whitespace separated, so only the trailing `x y z charge radius` columns are relied upon.
+/// Returns the centered atoms and their charges in the same order.
+pub fn load_pqr_molecule(path: &Path) -> (Vec<Vec4>, Vec<f32>) {
+    let data = load_pqr_data(path).unwrap_or_else(|e| panic!("{}", e));
+
+    (data.atoms, data.charges)
+}
+
+fn load_pqr_data(path: &Path) -> Result<MoleculeData> {
     let mut atoms = Vec::new();
-    let mut molecules = HashMap::new();
+    let mut charges = Vec::new();
+    let mut attributes = Vec::new();
 
-    let directory = path.parent().expect("File must be in a directory.");
-    for entry in fs::read_dir(directory).expect("read_dir call failed") {
-        let entry = entry.unwrap();
-        let molecule_path = entry.path();
+    for (line_number, line) in open_lines(path)? {
+        let line = line.map_err(|e| Error::io(path, e))?;
+        if !line.starts_with("ATOM") && !line.starts_with("HETATM") {
+            continue;
+        }
 
-        let molecule_path_str = molecule_path.to_str().unwrap();
-        if molecule_path_str.ends_with(".pdb") {
-            let pdb_name = molecule_path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .trim_end_matches(".pdb")
-                .to_ascii_uppercase();
-            let pdb_molecule = load_molecule(&molecule_path);
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 10 {
+            return Err(Error::parse(path, line_number, "expected at least 10 fields in a PQR atom record"));
+        }
 
-            molecules.insert(pdb_name, pdb_molecule);
+        let fields = &parts[parts.len() - 5..];
+        let x = parse_number::<f32>(path, line_number, fields[0], "x coordinate")?;
+        let y = parse_number::<f32>(path, line_number, fields[1], "y coordinate")?;
+        let z = parse_number::<f32>(path, line_number, fields[2], "z coordinate")?;
+        let charge = parse_number::<f32>(path, line_number, fields[3], "charge")?;
+        let radius = parse_number::<f32>(path, line_number, fields[4], "radius")?;
+
+        atoms.push(glm::vec4(x, y, z, radius));
+        charges.push(charge);
+
+        // The chain identifier is optional, which shifts the residue number
+        let chain_id = if parts.len() > 10 { parts[4] } else { "" };
+        attributes.push(rpdb::AtomAttributes {
+            element: element_from_atom_name(parts[2]),
+            chain_id: chain_id.to_string(),
+            residue_name: parts[3].to_string(),
+            residue_number: parts[parts.len() - 6].parse::<i32>().unwrap_or(0),
+            b_factor: 0.0,
+            occupancy: 1.0,
+        });
+    }
+
+    Ok(MoleculeData {
+        atoms: center_atoms(atoms),
+        charges,
+        attributes,
+    })
+}
+
+/// Van der Waals radius for an element symbol, using the same table as the PDB path.
+pub fn element_radius(element: &str) -> f32 {
+    match element.trim().to_ascii_uppercase().as_str() {
+        "C" => 1.548,
+        "H" => 1.100,
+        "N" => 1.400,
+        "O" => 1.348,
+        "P" => 1.880,
+        "S" => 1.880,
+        _ => 1.0,
+    }
+}
+
+// Splits a CIF data line into tokens, honouring single and double quoted values
+fn cif_tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = line.chars().collect();
+
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if chars[i] == '\'' || chars[i] == '"' {
+            // A quote only closes the value if it is followed by whitespace or the end of the line
+            let quote = chars[i];
+            let start = i + 1;
+            let mut end = start;
+            while end < chars.len() && !(chars[end] == quote && (end + 1 == chars.len() || chars[end + 1].is_whitespace())) {
+                end += 1;
+            }
+            tokens.push(chars[start..end].iter().collect());
+            i = end + 1;
+        } else {
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
         }
     }
 
-    let structure_file = File::open(path).expect("Could not open structure file.");
-    let structure_reader = BufReader::new(&structure_file);
-    for line in structure_reader.lines() {
-        if let Ok(line) = line {
-            let parts: Vec<&str> = line.split(' ').collect();
+    tokens
+}
 
-            if parts.len() == 9 {
-                let molecule_name = parts[0];
+/// Loads the atoms of an mmCIF/PDBx file from its `_atom_site` loop.
+///
+/// Only the first model is read. Radii are assigned from the element symbol like in `load_molecule`.
+pub fn load_cif_molecule(path: &Path) -> Vec<Vec4> {
+    load_cif_data(path).unwrap_or_else(|e| panic!("{}", e)).atoms
+}
 
-                let molecule_position = vec3(
-                    parts[1].parse::<f32>().unwrap(),
-                    parts[2].parse::<f32>().unwrap(),
-                    parts[3].parse::<f32>().unwrap(),
-                );
-                let molecule_quaternion = quat(
-                    -parts[7].parse::<f32>().unwrap(),
-                    parts[4].parse::<f32>().unwrap(),
-                    parts[5].parse::<f32>().unwrap(),
-                    -parts[6].parse::<f32>().unwrap(),
+fn load_cif_data(path: &Path) -> Result<MoleculeData> {
+    let mut atoms = Vec::new();
+    let mut attributes = Vec::new();
+
+    let mut in_loop = false;
+    let mut in_atom_site = false;
+    let mut columns: Vec<String> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut first_model: Option<String> = None;
+    for (line_number, line) in open_lines(path)? {
+        let line = line.map_err(|e| Error::io(path, e))?;
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if trimmed == "loop_" {
+            if in_atom_site {
+                break;
+            }
+            in_loop = true;
+            columns.clear();
+            continue;
+        }
+
+        if trimmed.starts_with('_') {
+            if in_atom_site && !row.is_empty() {
+                break;
+            }
+            if in_loop && trimmed.starts_with("_atom_site.") {
+                in_atom_site = true;
+                columns.push(
+                    trimmed
+                        .split_whitespace()
+                        .next()
+                        .unwrap()
+                        .trim_start_matches("_atom_site.")
+                        .to_string(),
                 );
+            } else if in_atom_site {
+                break;
+            } else {
+                in_loop = false;
+            }
+            continue;
+        }
 
-                let translation = translation(&(3333.33 * molecule_position));
-                let rotation = quat_to_mat4(&molecule_quaternion);
+        if trimmed.starts_with('#') || trimmed.starts_with("data_") {
+            if in_atom_site {
+                break;
+            }
+            in_loop = false;
+            continue;
+        }
+
+        if !in_atom_site {
+            continue;
+        }
 
-                let molecule_atoms: Vec<Vec4> = molecules[molecule_name]
-                    .clone()
-                    .iter()
-                    .map(|v| translation * rotation * v)
-                    .collect();
-                atoms.extend(molecule_atoms);
+        // Rows may be wrapped over several lines
+        row.extend(cif_tokens(trimmed));
+        if row.len() < columns.len() {
+            continue;
+        }
+        if row.len() > columns.len() {
+            return Err(Error::parse(
+                path,
+                line_number,
+                format!("expected {} values in an _atom_site row, found {}", columns.len(), row.len()),
+            ));
+        }
+
+        let column = |name: &str| columns.iter().position(|c| c == name);
+        let value = |name: &str| column(name).map(|i| row[i].as_str());
+
+        if let Some(model) = value("pdbx_PDB_model_num") {
+            match &first_model {
+                Some(first_model) if first_model != model => break,
+                None => first_model = Some(model.to_string()),
+                _ => {}
             }
         }
+
+        let coordinate = |name: &str| match value(name) {
+            Some(v) => parse_number::<f32>(path, line_number, v, name),
+            None => Err(Error::parse(path, line_number, format!("missing _atom_site.{} column", name))),
+        };
+        let x = coordinate("Cartn_x")?;
+        let y = coordinate("Cartn_y")?;
+        let z = coordinate("Cartn_z")?;
+        let element = value("type_symbol").unwrap_or("").to_ascii_uppercase();
+
+        atoms.push(glm::vec4(x, y, z, element_radius(&element)));
+        attributes.push(rpdb::AtomAttributes {
+            element,
+            chain_id: value("auth_asym_id").or(value("label_asym_id")).unwrap_or("").to_string(),
+            residue_name: value("label_comp_id").or(value("auth_comp_id")).unwrap_or("").to_string(),
+            residue_number: value("auth_seq_id")
+                .or(value("label_seq_id"))
+                .and_then(|v| v.parse::<i32>().ok())
+                .unwrap_or(0),
+            b_factor: value("B_iso_or_equiv").and_then(|v| v.parse::<f32>().ok()).unwrap_or(0.0),
+            occupancy: value("occupancy").and_then(|v| v.parse::<f32>().ok()).unwrap_or(1.0),
+        });
+        row.clear();
     }
 
-    atoms
+    Ok(MoleculeData {
+        atoms: center_atoms(atoms),
+        charges: Vec::new(),
+        attributes,
+    })
+}
+
+pub fn load_molecules(path: &Path) -> Vec<Vec4> {
+    try_load_molecules(path).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Loads a mesoscale structure file, instancing every `.pdb` molecule found next to it.
+pub fn try_load_molecules(path: &Path) -> Result<Vec<Vec4>> {
+    try_load_molecules_with_scale(path, mesoscale::DEFAULT_SCALE)
+}
+
+/// Like `try_load_molecules`, with the positions of the structure file multiplied by `scale`.
+pub fn try_load_molecules_with_scale(path: &Path, scale: f32) -> Result<Vec<Vec4>> {
+    let mut atoms = Vec::new();
+    let mut molecules = HashMap::new();
+
+    let directory = path.parent().ok_or_else(|| Error::format(path, "File must be in a directory."))?;
+    for entry in fs::read_dir(directory).map_err(|e| Error::io(directory, e))? {
+        let entry = entry.map_err(|e| Error::io(directory, e))?;
+        let molecule_path = entry.path();
+
+        let is_pdb = molecule_path.extension().and_then(|e| e.to_str()) == Some("pdb");
+        if is_pdb {
+            let pdb_name = molecule_path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| Error::format(&molecule_path, "File name is not valid UTF-8."))?
+                .to_ascii_uppercase();
+            let pdb_molecule = try_load_molecule(&molecule_path)?;
+
+            molecules.insert(pdb_name, pdb_molecule);
+        }
+    }
+
+    for (line_number, line) in open_lines(path)? {
+        let line = line.map_err(|e| Error::io(path, e))?;
+        let instance = match mesoscale::parse_instance(path, line_number, &line)? {
+            Some(instance) => instance,
+            None => continue,
+        };
+
+        let model_matrix = instance.model_matrix(scale);
+        let molecule = molecules
+            .get(&instance.name)
+            .ok_or_else(|| Error::parse(path, line_number, format!("unknown molecule '{}'", instance.name)))?;
+        atoms.extend(molecule.iter().map(|v| model_matrix * v));
+    }
+
+    Ok(atoms)
 }
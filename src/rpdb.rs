@@ -1,16 +1,46 @@
+use crate::error::{Error, Result};
 use glm::{Mat4, Vec3, Vec4};
 use nalgebra_glm as glm;
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub mod binary;
+pub mod hierarchy;
+pub mod validate;
+
+// Binary container is used for `.rpdb` files, RON for anything else
+fn is_binary_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| e.eq_ignore_ascii_case("rpdb"))
+}
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub struct BoundingBox {
     pub min: Vec3,
     pub max: Vec3,
 }
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AtomAttributes {
+    pub element: String,
+    pub chain_id: String,
+    pub residue_name: String,
+    pub residue_number: i32,
+    pub b_factor: f32,
+    pub occupancy: f32,
+}
 #[derive(Serialize, Deserialize)]
 pub struct MoleculeLod {
     max_radius: f32,
     atoms: Vec<Vec4>,
+    // Source atoms of every sphere, `members[members_offsets[i]..members_offsets[i + 1]]` belong to sphere `i`
+    #[serde(default)]
+    members_offsets: Vec<u32>,
+    #[serde(default)]
+    members: Vec<u32>,
+    // Geometric error against the first LOD, see `error`
+    #[serde(default)]
+    error: Option<f32>,
 }
 
 impl MoleculeLod {
@@ -23,7 +53,39 @@ impl MoleculeLod {
             }
         }
 
-        Self { max_radius, atoms }
+        Self {
+            max_radius,
+            atoms,
+            members_offsets: Vec::new(),
+            members: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Creates a LOD whose spheres remember the source atoms they were built from.
+    ///
+    /// `clusters[i]` is the index of the sphere containing source atom `i`.
+    pub fn with_members(atoms: Vec<Vec4>, clusters: &[u32]) -> Self {
+        let mut lod = Self::new(atoms);
+
+        let mut members_offsets = vec![0u32; lod.atoms.len() + 1];
+        for &cluster in clusters {
+            members_offsets[cluster as usize + 1] += 1;
+        }
+        for i in 1..members_offsets.len() {
+            members_offsets[i] += members_offsets[i - 1];
+        }
+
+        let mut fill = members_offsets.clone();
+        let mut members = vec![0u32; clusters.len()];
+        for (atom, &cluster) in clusters.iter().enumerate() {
+            members[fill[cluster as usize] as usize] = atom as u32;
+            fill[cluster as usize] += 1;
+        }
+
+        lod.members_offsets = members_offsets;
+        lod.members = members;
+        lod
     }
 
     pub fn max_radius(&self) -> f32 {
@@ -33,12 +95,58 @@ impl MoleculeLod {
     pub fn atoms(&self) -> &[Vec4] {
         &self.atoms
     }
+
+    /// Upper bound on how far the surface of this LOD strays from the atoms it stands for (in Å).
+    ///
+    /// `None` for files written before errors were stored, such LODs are never picked automatically.
+    pub fn error(&self) -> Option<f32> {
+        self.error
+    }
+
+    pub fn set_error(&mut self, error: f32) {
+        self.error = Some(error);
+    }
+
+    /// Indices of the source atoms that make up a sphere, `None` if this LOD stores no mapping (e.g. the first LOD).
+    pub fn members(&self, sphere: usize) -> Option<&[u32]> {
+        if self.members_offsets.is_empty() {
+            return None;
+        }
+
+        let start = self.members_offsets[sphere] as usize;
+        let end = self.members_offsets[sphere + 1] as usize;
+        Some(&self.members[start..end])
+    }
+}
+/// Occluders of a molecule in its own coordinates, built offline by `occluder_builder` from a voxelization of the
+/// first LOD.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Occluders {
+    /// Size of the voxel grid the occluders were extracted from.
+    pub grid_size: i32,
+    /// Non-overlapping boxes inside the molecule, largest first.
+    pub boxes: Vec<BoundingBox>,
+    /// Fraction of the solid voxels covered by the boxes.
+    pub boxes_coverage: f32,
+    /// Triangle soup of the planar occluders, three vertices per triangle.
+    pub triangles: Vec<Vec3>,
+    /// Total area of the triangles (in Å²).
+    pub triangles_area: f32,
 }
 #[derive(Serialize, Deserialize)]
 pub struct Molecule {
     pub name: String,
     pub bounding_box: BoundingBox,
     pub lods: Vec<MoleculeLod>,
+    /// Partial charges of the atoms in the first LOD. Empty if the source file carried no charges.
+    #[serde(default)]
+    pub charges: Vec<f32>,
+    /// Per-atom metadata of the first LOD. Empty if it was not loaded.
+    #[serde(default)]
+    pub attributes: Vec<AtomAttributes>,
+    /// Precomputed occluders, `None` if they were not built.
+    #[serde(default)]
+    pub occluders: Option<Occluders>,
 }
 
 impl Molecule {
@@ -53,9 +161,102 @@ impl Molecule {
     pub fn lods(&self) -> &[MoleculeLod] {
         &self.lods
     }
+
+    pub fn charges(&self) -> &[f32] {
+        &self.charges
+    }
+
+    pub fn attributes(&self) -> &[AtomAttributes] {
+        &self.attributes
+    }
+
+    pub fn occluders(&self) -> Option<&Occluders> {
+        self.occluders.as_ref()
+    }
+
+    /// Loads a molecule stored either as RON or in the binary container (detected by its magic number).
+    pub fn load(path: &Path) -> Self {
+        Self::try_load(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+
+        if binary::is_binary(&bytes) {
+            binary::read_molecule(&bytes).map_err(|e| Error::format(path, e.to_string()))
+        } else {
+            ron::de::from_bytes(&bytes).map_err(|e| Error::format(path, e.to_string()))
+        }
+    }
+
+    /// Writes the molecule in the binary container if the path ends with `.rpdb`, as pretty RON otherwise.
+    pub fn save(&self, path: &Path) {
+        self.try_save(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_save(&self, path: &Path) -> Result<()> {
+        if is_binary_path(path) {
+            let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+            binary::write_molecule(std::io::BufWriter::new(file), self).map_err(|e| Error::io(path, e))
+        } else {
+            let s = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|e| Error::format(path, e.to_string()))?;
+            std::fs::write(path, s).map_err(|e| Error::io(path, e))
+        }
+    }
 }
 #[derive(Serialize, Deserialize)]
 pub struct Structure {
     pub names: Vec<String>,
     pub model_matrices: Vec<Mat4>,
+    /// Order in which the instances were populated, empty if the source carried none.
+    #[serde(default)]
+    pub sequence_ids: Vec<u64>,
+    /// Other structures instanced by this one, see `hierarchy`. Their files are found like the molecules.
+    #[serde(default)]
+    pub structure_names: Vec<String>,
+    #[serde(default)]
+    pub structure_model_matrices: Vec<Mat4>,
+}
+
+impl Structure {
+    /// File of a molecule (or nested structure) referenced by a structure: `<name>.rpdb` next to the structure if it exists,
+    /// `<name>.ron` otherwise.
+    pub fn molecule_path(structure_path: &Path, name: &str) -> PathBuf {
+        let binary = structure_path.with_file_name(format!("{}.rpdb", name));
+        if binary.exists() {
+            binary
+        } else {
+            structure_path.with_file_name(format!("{}.ron", name))
+        }
+    }
+
+    /// Loads a structure stored either as RON or in the binary container (detected by its magic number).
+    pub fn load(path: &Path) -> Self {
+        Self::try_load(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+
+        if binary::is_binary(&bytes) {
+            binary::read_structure(&bytes).map_err(|e| Error::format(path, e.to_string()))
+        } else {
+            ron::de::from_bytes(&bytes).map_err(|e| Error::format(path, e.to_string()))
+        }
+    }
+
+    /// Writes the structure in the binary container if the path ends with `.rpdb`, as RON otherwise.
+    pub fn save(&self, path: &Path) {
+        self.try_save(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_save(&self, path: &Path) -> Result<()> {
+        if is_binary_path(path) {
+            let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+            binary::write_structure(std::io::BufWriter::new(file), self).map_err(|e| Error::io(path, e))
+        } else {
+            let s = ron::ser::to_string(self).map_err(|e| Error::format(path, e.to_string()))?;
+            std::fs::write(path, s).map_err(|e| Error::io(path, e))
+        }
+    }
 }
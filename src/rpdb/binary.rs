@@ -0,0 +1,484 @@
+//! Versioned little-endian binary container for `Molecule` and `Structure`.
+//!
+//! Layout: a 16 byte header (`RPDB` magic, format version, payload kind, reserved) followed by the payload.
+//! Version 1 predates the LOD errors, structure sequence IDs, nested structures and molecule occluders, which all
+//! came with version 2. Only the current version is read.
+//! Every bulk array (atoms, members, matrices) starts on a 16 byte boundary of the file, so a memory-mapped
+//! file can hand its slices directly to `create_buffer_with_data`.
+use super::{AtomAttributes, BoundingBox, Molecule, MoleculeLod, Occluders, Structure};
+use nalgebra_glm as glm;
+use std::io::{Error, ErrorKind, Result, Write};
+
+pub const MAGIC: [u8; 4] = *b"RPDB";
+pub const VERSION: u32 = 2;
+
+pub const KIND_MOLECULE: u32 = 1;
+pub const KIND_STRUCTURE: u32 = 2;
+
+const ALIGNMENT: usize = 16;
+
+/// Returns true if the bytes start with the binary container header.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[0..4] == MAGIC
+}
+
+struct Writer<W: Write> {
+    inner: W,
+    offset: usize,
+}
+
+impl<W: Write> Writer<W> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.inner.write_all(bytes)?;
+        self.offset += bytes.len();
+        Ok(())
+    }
+
+    fn u32(&mut self, value: u32) -> Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn i32(&mut self, value: i32) -> Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn f32(&mut self, value: f32) -> Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn string(&mut self, value: &str) -> Result<()> {
+        self.u32(value.len() as u32)?;
+        self.bytes(value.as_bytes())?;
+        self.align(4)
+    }
+
+    fn align(&mut self, alignment: usize) -> Result<()> {
+        let padding = (alignment - self.offset % alignment) % alignment;
+        self.bytes(&[0u8; ALIGNMENT][..padding])
+    }
+
+    fn header(&mut self, kind: u32) -> Result<()> {
+        self.bytes(&MAGIC)?;
+        self.u32(VERSION)?;
+        self.u32(kind)?;
+        self.u32(0)
+    }
+}
+
+/// Borrowed view of a binary molecule. The slices point into the source bytes and are little-endian.
+pub struct MoleculeView<'a> {
+    pub name: &'a str,
+    pub bounding_box: BoundingBox,
+    pub lods: Vec<MoleculeLodView<'a>>,
+    pub charges: &'a [u8],
+    pub attributes: Vec<AtomAttributes>,
+    pub occluders: Option<OccludersView<'a>>,
+}
+
+pub struct OccludersView<'a> {
+    pub grid_size: i32,
+    pub boxes_coverage: f32,
+    pub triangles_area: f32,
+    /// `min` and `max` corners, six f32 per box
+    pub boxes: &'a [u8],
+    /// `xyz` f32 triplets, three vertices per triangle
+    pub triangles: &'a [u8],
+}
+
+pub struct MoleculeLodView<'a> {
+    pub max_radius: f32,
+    pub error: Option<f32>,
+    /// `xyzr` f32 quadruplets
+    pub atoms: &'a [u8],
+    pub members_offsets: &'a [u8],
+    pub members: &'a [u8],
+}
+
+impl<'a> MoleculeLodView<'a> {
+    pub fn atoms_len(&self) -> usize {
+        self.atoms.len() / 16
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "rpdb binary file is truncated")
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() - self.offset {
+            return Err(truncated());
+        }
+
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    // Element count of an array whose elements take at least `element_size` bytes. Counts that cannot fit in the
+    // rest of the file are rejected before anything is allocated for them.
+    fn count(&mut self, element_size: usize) -> Result<usize> {
+        let len = self.u32()? as usize;
+        match len.checked_mul(element_size) {
+            Some(size) if size <= self.bytes.len() - self.offset => Ok(len),
+            _ => Err(truncated()),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(value))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn string(&mut self) -> Result<&'a str> {
+        let len = self.count(1)?;
+        let bytes = self.bytes(len)?;
+        self.align(4)?;
+
+        std::str::from_utf8(bytes).map_err(|_| invalid("string is not valid UTF-8"))
+    }
+
+    fn align(&mut self, alignment: usize) -> Result<()> {
+        let padding = (alignment - self.offset % alignment) % alignment;
+        self.bytes(padding).map(|_| ())
+    }
+
+    fn header(&mut self, kind: u32) -> Result<()> {
+        if self.bytes(4)? != MAGIC {
+            return Err(invalid("missing RPDB magic number"));
+        }
+
+        let version = self.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported rpdb version {} (expected {})", version, VERSION)));
+        }
+
+        if self.u32()? != kind {
+            return Err(invalid("unexpected rpdb payload kind"));
+        }
+
+        self.u32().map(|_| ())
+    }
+}
+
+fn f32s(bytes: &[u8]) -> impl Iterator<Item = f32> + '_ {
+    bytes.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+}
+
+fn u32s(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes.chunks_exact(4).map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+}
+
+pub fn write_molecule<W: Write>(writer: W, molecule: &Molecule) -> Result<()> {
+    let mut w = Writer { inner: writer, offset: 0 };
+
+    w.header(KIND_MOLECULE)?;
+    w.string(&molecule.name)?;
+    for v in molecule.bounding_box.min.iter().chain(molecule.bounding_box.max.iter()) {
+        w.f32(*v)?;
+    }
+
+    w.u32(molecule.lods.len() as u32)?;
+    for lod in &molecule.lods {
+        w.f32(lod.max_radius)?;
+        // NaN stands for an unknown error
+        w.f32(lod.error.unwrap_or(std::f32::NAN))?;
+        w.u32(lod.atoms.len() as u32)?;
+        w.u32(lod.members_offsets.len() as u32)?;
+        w.u32(lod.members.len() as u32)?;
+
+        w.align(ALIGNMENT)?;
+        for atom in &lod.atoms {
+            w.f32(atom.x)?;
+            w.f32(atom.y)?;
+            w.f32(atom.z)?;
+            w.f32(atom.w)?;
+        }
+        w.align(ALIGNMENT)?;
+        for offset in &lod.members_offsets {
+            w.u32(*offset)?;
+        }
+        w.align(ALIGNMENT)?;
+        for member in &lod.members {
+            w.u32(*member)?;
+        }
+    }
+
+    w.u32(molecule.charges.len() as u32)?;
+    w.align(ALIGNMENT)?;
+    for charge in &molecule.charges {
+        w.f32(*charge)?;
+    }
+
+    w.u32(molecule.attributes.len() as u32)?;
+    for attributes in &molecule.attributes {
+        w.string(&attributes.element)?;
+        w.string(&attributes.chain_id)?;
+        w.string(&attributes.residue_name)?;
+        w.i32(attributes.residue_number)?;
+        w.f32(attributes.b_factor)?;
+        w.f32(attributes.occupancy)?;
+    }
+
+    // Occluders are preceded by a flag, 0 if there are none
+    w.u32(molecule.occluders.is_some() as u32)?;
+    if let Some(occluders) = &molecule.occluders {
+        w.i32(occluders.grid_size)?;
+        w.f32(occluders.boxes_coverage)?;
+        w.f32(occluders.triangles_area)?;
+
+        w.u32(occluders.boxes.len() as u32)?;
+        w.align(ALIGNMENT)?;
+        for b in &occluders.boxes {
+            for v in b.min.iter().chain(b.max.iter()) {
+                w.f32(*v)?;
+            }
+        }
+
+        w.u32(occluders.triangles.len() as u32)?;
+        w.align(ALIGNMENT)?;
+        for vertex in &occluders.triangles {
+            for v in vertex.iter() {
+                w.f32(*v)?;
+            }
+        }
+    }
+
+    w.inner.flush()
+}
+
+/// Parses a binary molecule without copying the bulk arrays.
+pub fn view_molecule(bytes: &[u8]) -> Result<MoleculeView<'_>> {
+    let mut r = Reader { bytes, offset: 0 };
+
+    r.header(KIND_MOLECULE)?;
+    let name = r.string()?;
+    let min = glm::vec3(r.f32()?, r.f32()?, r.f32()?);
+    let max = glm::vec3(r.f32()?, r.f32()?, r.f32()?);
+
+    // Every LOD starts with five 4 byte fields
+    let lods_len = r.count(20)?;
+    let mut lods = Vec::with_capacity(lods_len);
+    for _ in 0..lods_len {
+        let max_radius = r.f32()?;
+        let error = Some(r.f32()?).filter(|e| !e.is_nan());
+        let atoms_len = r.count(16)?;
+        let members_offsets_len = r.count(4)?;
+        let members_len = r.count(4)?;
+
+        r.align(ALIGNMENT)?;
+        let atoms = r.bytes(atoms_len * 16)?;
+        r.align(ALIGNMENT)?;
+        let members_offsets = r.bytes(members_offsets_len * 4)?;
+        r.align(ALIGNMENT)?;
+        let members = r.bytes(members_len * 4)?;
+
+        // `MoleculeLod::members` slices the members with consecutive offsets, one range per sphere
+        if members_offsets_len != 0 {
+            let offsets: Vec<u32> = u32s(members_offsets).collect();
+            if offsets.len() != atoms_len + 1
+                || offsets.windows(2).any(|w| w[0] > w[1])
+                || offsets[offsets.len() - 1] as usize > members_len
+            {
+                return Err(invalid("LOD members offsets do not match its spheres and members"));
+            }
+        }
+
+        lods.push(MoleculeLodView {
+            max_radius,
+            error,
+            atoms,
+            members_offsets,
+            members,
+        });
+    }
+
+    let charges_len = r.count(4)?;
+    r.align(ALIGNMENT)?;
+    let charges = r.bytes(charges_len * 4)?;
+
+    // Three string lengths and three 4 byte fields per atom
+    let attributes_len = r.count(24)?;
+    let mut attributes = Vec::with_capacity(attributes_len);
+    for _ in 0..attributes_len {
+        attributes.push(AtomAttributes {
+            element: r.string()?.to_string(),
+            chain_id: r.string()?.to_string(),
+            residue_name: r.string()?.to_string(),
+            residue_number: r.i32()?,
+            b_factor: r.f32()?,
+            occupancy: r.f32()?,
+        });
+    }
+
+    let occluders = if r.u32()? != 0 {
+        let grid_size = r.i32()?;
+        let boxes_coverage = r.f32()?;
+        let triangles_area = r.f32()?;
+
+        let boxes_len = r.count(24)?;
+        r.align(ALIGNMENT)?;
+        let boxes = r.bytes(boxes_len * 24)?;
+
+        let triangles_len = r.count(12)?;
+        r.align(ALIGNMENT)?;
+        let triangles = r.bytes(triangles_len * 12)?;
+
+        Some(OccludersView {
+            grid_size,
+            boxes_coverage,
+            triangles_area,
+            boxes,
+            triangles,
+        })
+    } else {
+        None
+    };
+
+    Ok(MoleculeView {
+        name,
+        bounding_box: BoundingBox { min, max },
+        lods,
+        charges,
+        attributes,
+        occluders,
+    })
+}
+
+pub fn read_molecule(bytes: &[u8]) -> Result<Molecule> {
+    let view = view_molecule(bytes)?;
+
+    let lods = view
+        .lods
+        .iter()
+        .map(|lod| {
+            let values: Vec<f32> = f32s(lod.atoms).collect();
+            let atoms = values.chunks_exact(4).map(|a| glm::vec4(a[0], a[1], a[2], a[3])).collect();
+
+            MoleculeLod {
+                max_radius: lod.max_radius,
+                atoms,
+                members_offsets: u32s(lod.members_offsets).collect(),
+                members: u32s(lod.members).collect(),
+                error: lod.error,
+            }
+        })
+        .collect();
+
+    Ok(Molecule {
+        name: view.name.to_string(),
+        bounding_box: view.bounding_box,
+        lods,
+        charges: f32s(view.charges).collect(),
+        attributes: view.attributes,
+        occluders: view.occluders.map(|occluders| {
+            let boxes: Vec<f32> = f32s(occluders.boxes).collect();
+            let triangles: Vec<f32> = f32s(occluders.triangles).collect();
+
+            Occluders {
+                grid_size: occluders.grid_size,
+                boxes: boxes
+                    .chunks_exact(6)
+                    .map(|b| BoundingBox {
+                        min: glm::vec3(b[0], b[1], b[2]),
+                        max: glm::vec3(b[3], b[4], b[5]),
+                    })
+                    .collect(),
+                boxes_coverage: occluders.boxes_coverage,
+                triangles: triangles.chunks_exact(3).map(|v| glm::vec3(v[0], v[1], v[2])).collect(),
+                triangles_area: occluders.triangles_area,
+            }
+        }),
+    })
+}
+
+fn write_instances<W: Write>(w: &mut Writer<W>, names: &[String], model_matrices: &[glm::Mat4]) -> Result<()> {
+    w.u32(names.len() as u32)?;
+    for name in names {
+        w.string(name)?;
+    }
+
+    w.u32(model_matrices.len() as u32)?;
+    w.align(ALIGNMENT)?;
+    for matrix in model_matrices {
+        for v in matrix.as_slice() {
+            w.f32(*v)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_instances(r: &mut Reader) -> Result<(Vec<String>, Vec<glm::Mat4>)> {
+    // Every name takes at least its length
+    let names_len = r.count(4)?;
+    let mut names = Vec::with_capacity(names_len);
+    for _ in 0..names_len {
+        names.push(r.string()?.to_string());
+    }
+
+    let matrices_len = r.count(64)?;
+    r.align(ALIGNMENT)?;
+    let values: Vec<f32> = f32s(r.bytes(matrices_len * 64)?).collect();
+    let model_matrices = values.chunks_exact(16).map(glm::make_mat4).collect();
+
+    Ok((names, model_matrices))
+}
+
+pub fn write_structure<W: Write>(writer: W, structure: &Structure) -> Result<()> {
+    let mut w = Writer { inner: writer, offset: 0 };
+
+    w.header(KIND_STRUCTURE)?;
+    write_instances(&mut w, &structure.names, &structure.model_matrices)?;
+
+    w.u32(structure.sequence_ids.len() as u32)?;
+    for sequence_id in &structure.sequence_ids {
+        w.u32(*sequence_id as u32)?;
+        w.u32((*sequence_id >> 32) as u32)?;
+    }
+
+    write_instances(&mut w, &structure.structure_names, &structure.structure_model_matrices)?;
+
+    w.inner.flush()
+}
+
+pub fn read_structure(bytes: &[u8]) -> Result<Structure> {
+    let mut r = Reader { bytes, offset: 0 };
+
+    r.header(KIND_STRUCTURE)?;
+    let (names, model_matrices) = read_instances(&mut r)?;
+
+    let sequence_ids_len = r.count(8)?;
+    let mut sequence_ids = Vec::with_capacity(sequence_ids_len);
+    for _ in 0..sequence_ids_len {
+        sequence_ids.push(r.u32()? as u64 | (r.u32()? as u64) << 32);
+    }
+
+    let (structure_names, structure_model_matrices) = read_instances(&mut r)?;
+
+    Ok(Structure {
+        names,
+        model_matrices,
+        sequence_ids,
+        structure_names,
+        structure_model_matrices,
+    })
+}
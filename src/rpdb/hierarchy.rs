@@ -0,0 +1,243 @@
+//! Nested structures: a structure instances molecules and other structures, each with its own model matrix.
+//!
+//! Names are looked up next to the structure that references them (`Structure::molecule_path`), so a name
+//! may carry a relative directory. Flattening rewrites the names of nested instances to stay relative to the
+//! top-level structure.
+use super::Structure;
+use crate::error::{Error, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Structure with every nested structure replaced by its molecule instances.
+pub struct ResolvedStructure {
+    /// Flat structure of molecule instances with their final model matrices. Sequence IDs are only kept
+    /// when nothing was nested.
+    pub structure: Structure,
+    /// Total number of instances of every nested structure, including the indirect ones.
+    pub structure_counts: BTreeMap<String, usize>,
+}
+
+struct Flat {
+    structure: Structure,
+    structure_counts: BTreeMap<String, usize>,
+}
+
+#[derive(Default)]
+struct Resolver {
+    // Files being resolved, from the top-level one down
+    stack: Vec<PathBuf>,
+    // Every nested file is loaded and flattened once
+    cache: HashMap<PathBuf, Rc<Flat>>,
+}
+
+// Joins the directory part of a reference with a name found inside the referenced file
+fn prefixed(reference: &str, name: &str) -> String {
+    match Path::new(reference).parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(directory) => directory.join(name).to_string_lossy().into_owned(),
+        None => name.to_string(),
+    }
+}
+
+impl Resolver {
+    fn load(&mut self, path: &Path) -> Result<Rc<Flat>> {
+        let key = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        if let Some(position) = self.stack.iter().position(|p| *p == key) {
+            let cycle: Vec<String> = self.stack[position..]
+                .iter()
+                .chain(std::iter::once(&key))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(Error::format(path, format!("structure cycle {}", cycle.join(" -> "))));
+        }
+        if let Some(flat) = self.cache.get(&key) {
+            return Ok(flat.clone());
+        }
+
+        let structure = Structure::try_load(path)?;
+
+        self.stack.push(key.clone());
+        let flat = self.flatten(&structure, path);
+        self.stack.pop();
+
+        let flat = Rc::new(flat?);
+        self.cache.insert(key, flat.clone());
+        Ok(flat)
+    }
+
+    fn flatten(&mut self, structure: &Structure, path: &Path) -> Result<Flat> {
+        if structure.structure_names.len() != structure.structure_model_matrices.len() {
+            return Err(Error::format(
+                path,
+                format!(
+                    "{} nested structure names but {} model matrices",
+                    structure.structure_names.len(),
+                    structure.structure_model_matrices.len()
+                ),
+            ));
+        }
+
+        let mut names = structure.names.clone();
+        let mut model_matrices = structure.model_matrices.clone();
+        let mut structure_counts = BTreeMap::new();
+
+        for (name, matrix) in structure.structure_names.iter().zip(structure.structure_model_matrices.iter()) {
+            let nested = self.load(&Structure::molecule_path(path, name))?;
+
+            *structure_counts.entry(name.clone()).or_insert(0) += 1;
+            for (nested_name, count) in &nested.structure_counts {
+                *structure_counts.entry(prefixed(name, nested_name)).or_insert(0) += count;
+            }
+
+            names.extend(nested.structure.names.iter().map(|n| prefixed(name, n)));
+            model_matrices.extend(nested.structure.model_matrices.iter().map(|m| matrix * m));
+        }
+
+        let sequence_ids = if structure.structure_names.is_empty() {
+            structure.sequence_ids.clone()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Flat {
+            structure: Structure {
+                names,
+                model_matrices,
+                sequence_ids,
+                structure_names: Vec::new(),
+                structure_model_matrices: Vec::new(),
+            },
+            structure_counts,
+        })
+    }
+}
+
+/// Flattens a structure stored at `path`, whose nested structures are looked up next to it.
+pub fn flatten(structure: &Structure, path: &Path) -> Result<ResolvedStructure> {
+    let mut resolver = Resolver::default();
+    resolver
+        .stack
+        .push(std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+
+    let flat = resolver.flatten(structure, path)?;
+
+    Ok(ResolvedStructure {
+        structure: flat.structure,
+        structure_counts: flat.structure_counts,
+    })
+}
+
+/// Loads a structure and resolves its nested structures recursively. Cycles are reported as errors.
+pub fn resolve_structure(path: &Path) -> Result<ResolvedStructure> {
+    flatten(&Structure::try_load(path)?, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glm::{vec3, Mat4};
+    use nalgebra_glm as glm;
+
+    // Empty directory for the structures of a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rpdb-hierarchy-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn save(path: &Path, molecules: &[(&str, Mat4)], structures: &[(&str, Mat4)]) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        Structure {
+            names: molecules.iter().map(|(name, _)| name.to_string()).collect(),
+            model_matrices: molecules.iter().map(|(_, matrix)| *matrix).collect(),
+            sequence_ids: Vec::new(),
+            structure_names: structures.iter().map(|(name, _)| name.to_string()).collect(),
+            structure_model_matrices: structures.iter().map(|(_, matrix)| *matrix).collect(),
+        }
+        .try_save(path)
+        .unwrap();
+    }
+
+    fn translation(x: f32, y: f32, z: f32) -> Mat4 {
+        glm::translation(&vec3(x, y, z))
+    }
+
+    fn assert_cycle(path: &Path) {
+        match resolve_structure(path) {
+            Err(e) => assert!(e.to_string().contains("structure cycle"), "{}", e),
+            Ok(_) => panic!("{} resolved despite its cycle", path.display()),
+        }
+    }
+
+    #[test]
+    fn self_reference_is_a_cycle() {
+        let dir = test_dir("self");
+        save(&dir.join("A.ron"), &[("M", Mat4::identity())], &[("A", Mat4::identity())]);
+
+        assert_cycle(&dir.join("A.ron"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn indirect_reference_is_a_cycle() {
+        let dir = test_dir("indirect");
+        save(&dir.join("A.ron"), &[("M", Mat4::identity())], &[("B", translation(1.0, 0.0, 0.0))]);
+        save(&dir.join("B.ron"), &[], &[("A", translation(0.0, 1.0, 0.0))]);
+
+        assert_cycle(&dir.join("A.ron"));
+        assert_cycle(&dir.join("B.ron"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn two_levels_flatten_to_molecule_instances() {
+        let dir = test_dir("levels");
+        let (left, right) = (translation(-10.0, 0.0, 0.0), translation(10.0, 0.0, 0.0));
+        let scale = glm::scaling(&vec3(2.0, 2.0, 2.0));
+        let (lower, upper) = (translation(0.0, -1.0, 0.0), translation(0.0, 1.0, 0.0));
+        let offset = translation(0.0, 0.0, 5.0);
+
+        // Top instances "sub/Mid" twice, every Mid instances a molecule and "Leaf" twice
+        save(
+            &dir.join("Top.ron"),
+            &[("T", Mat4::identity())],
+            &[("sub/Mid", left), ("sub/Mid", right)],
+        );
+        save(&dir.join("sub/Mid.ron"), &[("M", scale)], &[("Leaf", lower), ("Leaf", upper)]);
+        save(&dir.join("sub/Leaf.ron"), &[("L", offset)], &[]);
+
+        let resolved = resolve_structure(&dir.join("Top.ron")).unwrap();
+        let counts: Vec<(&str, usize)> = resolved
+            .structure_counts
+            .iter()
+            .map(|(name, count)| (name.as_str(), *count))
+            .collect();
+        assert_eq!(counts, vec![("sub/Leaf", 4), ("sub/Mid", 2)]);
+
+        let structure = &resolved.structure;
+        assert!(structure.structure_names.is_empty() && structure.structure_model_matrices.is_empty());
+        assert_eq!(structure.names, vec!["T", "sub/M", "sub/L", "sub/L", "sub/M", "sub/L", "sub/L"]);
+
+        let expected = [
+            Mat4::identity(),
+            left * scale,
+            left * lower * offset,
+            left * upper * offset,
+            right * scale,
+            right * lower * offset,
+            right * upper * offset,
+        ];
+        assert_eq!(structure.model_matrices.len(), expected.len());
+        for (matrix, expected) in structure.model_matrices.iter().zip(expected.iter()) {
+            assert!(
+                matrix.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-5),
+                "{} != {}",
+                matrix,
+                expected
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! Consistency checks of a structure against the molecule files it refers to.
+use super::hierarchy;
+use super::{BoundingBox, Molecule, Structure};
+use glm::{Mat4, Vec3};
+use nalgebra_glm as glm;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// Allowed deviation of the rotation columns from unit length and orthogonality
+const ROTATION_TOLERANCE: f32 = 1.0e-3;
+
+pub struct ValidationReport {
+    /// Every problem found, an empty list means the structure is consistent.
+    pub problems: Vec<String>,
+    /// Number of instances of every molecule, nested structures included.
+    pub instance_counts: BTreeMap<String, usize>,
+    /// Number of instances of every nested structure.
+    pub structure_counts: BTreeMap<String, usize>,
+    /// Atoms (spheres) of the whole structure for every LOD. Molecules with fewer LODs count their last one.
+    pub atom_counts: Vec<usize>,
+    /// Bounding box of the transformed molecule bounding boxes, `None` if no molecule could be loaded.
+    pub bounding_box: Option<BoundingBox>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Describes why the matrix is not a rigid transformation (rotation and translation), `None` if it is.
+pub fn check_model_matrix(matrix: &Mat4) -> Option<String> {
+    if matrix.iter().any(|v| !v.is_finite()) {
+        return Some(String::from("matrix is not finite"));
+    }
+
+    let last_row = matrix.row(3);
+    if last_row[0] != 0.0 || last_row[1] != 0.0 || last_row[2] != 0.0 || (last_row[3] - 1.0).abs() > ROTATION_TOLERANCE {
+        return Some(format!(
+            "matrix is not affine, last row is [{}, {}, {}, {}]",
+            last_row[0], last_row[1], last_row[2], last_row[3]
+        ));
+    }
+
+    let columns: Vec<Vec3> = (0..3).map(|i| matrix.column(i).xyz()).collect();
+    for (i, column) in columns.iter().enumerate() {
+        if (glm::length(column) - 1.0).abs() > ROTATION_TOLERANCE {
+            return Some(format!("rotation column {} has length {}", i, glm::length(column)));
+        }
+    }
+    for (i, j) in &[(0, 1), (0, 2), (1, 2)] {
+        if glm::dot(&columns[*i], &columns[*j]).abs() > ROTATION_TOLERANCE {
+            return Some(format!("rotation columns {} and {} are not orthogonal", i, j));
+        }
+    }
+    if glm::dot(&glm::cross(&columns[0], &columns[1]), &columns[2]) < 0.0 {
+        return Some(String::from("rotation is a reflection"));
+    }
+
+    None
+}
+
+fn transformed_bounding_box(bounding_box: &BoundingBox, matrix: &Mat4) -> BoundingBox {
+    let mut min = glm::vec3(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+    let mut max = -min;
+    for corner in 0..8 {
+        let point = glm::vec3(
+            if corner & 1 == 0 { bounding_box.min.x } else { bounding_box.max.x },
+            if corner & 2 == 0 { bounding_box.min.y } else { bounding_box.max.y },
+            if corner & 4 == 0 { bounding_box.min.z } else { bounding_box.max.z },
+        );
+        let point = (matrix * glm::vec4(point.x, point.y, point.z, 1.0)).xyz();
+        min = glm::min2(&min, &point);
+        max = glm::max2(&max, &point);
+    }
+
+    BoundingBox { min, max }
+}
+
+/// Checks a structure stored at `structure_path` against the molecule files next to it.
+///
+/// Nested structures are resolved first. If that fails, the problem is reported and only the top-level instances are checked.
+pub fn validate_structure(structure: &Structure, structure_path: &Path) -> ValidationReport {
+    let mut problems = Vec::new();
+
+    let resolved = match hierarchy::flatten(structure, structure_path) {
+        Ok(resolved) => Some(resolved),
+        Err(e) => {
+            problems.push(e.to_string());
+            None
+        }
+    };
+    let structure_counts = resolved.as_ref().map(|r| r.structure_counts.clone()).unwrap_or_default();
+    let structure = resolved.as_ref().map_or(structure, |r| &r.structure);
+
+    if structure.names.len() != structure.model_matrices.len() {
+        problems.push(format!(
+            "{} names but {} model matrices",
+            structure.names.len(),
+            structure.model_matrices.len()
+        ));
+    }
+    if !structure.sequence_ids.is_empty() && structure.sequence_ids.len() != structure.names.len() {
+        problems.push(format!(
+            "{} names but {} sequence IDs",
+            structure.names.len(),
+            structure.sequence_ids.len()
+        ));
+    }
+
+    let mut instance_counts = BTreeMap::new();
+    for name in &structure.names {
+        *instance_counts.entry(name.clone()).or_insert(0) += 1;
+    }
+
+    // Every molecule is loaded once, failures are reported once
+    let mut molecules: HashMap<&str, Molecule> = HashMap::new();
+    for name in instance_counts.keys() {
+        let path = Structure::molecule_path(structure_path, name);
+        if !path.exists() {
+            problems.push(format!("molecule '{}' not found, expected {}", name, path.display()));
+            continue;
+        }
+
+        match Molecule::try_load(&path) {
+            Ok(molecule) if molecule.lods().is_empty() => problems.push(format!("{} has no LODs", path.display())),
+            Ok(molecule) => {
+                molecules.insert(name, molecule);
+            }
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+
+    let lods_len = molecules.values().map(|m| m.lods().len()).max().unwrap_or(0);
+    let mut atom_counts = vec![0; lods_len];
+    let mut bounding_box: Option<BoundingBox> = None;
+
+    for (instance, (name, matrix)) in structure.names.iter().zip(structure.model_matrices.iter()).enumerate() {
+        if let Some(problem) = check_model_matrix(matrix) {
+            problems.push(format!("instance {} ({}): {}", instance, name, problem));
+        }
+
+        if let Some(molecule) = molecules.get(name.as_str()) {
+            for (lod, count) in atom_counts.iter_mut().enumerate() {
+                *count += molecule.lods()[lod.min(molecule.lods().len() - 1)].atoms().len();
+            }
+
+            let instance_box = transformed_bounding_box(molecule.bounding_box(), matrix);
+            bounding_box = Some(match bounding_box {
+                Some(b) => BoundingBox {
+                    min: glm::min2(&b.min, &instance_box.min),
+                    max: glm::max2(&b.max, &instance_box.max),
+                },
+                None => instance_box,
+            });
+        }
+    }
+
+    ValidationReport {
+        problems,
+        instance_counts,
+        structure_counts,
+        atom_counts,
+        bounding_box,
+    }
+}
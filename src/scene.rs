@@ -0,0 +1,152 @@
+//! Structure loaded together with its molecules, packed the way the viewers upload it to the GPU.
+//!
+//! Atoms of every LOD of every molecule type are stored one after another as `xyzr` floats, instances are
+//! grouped by molecule type. Matrices are packed column-major.
+use crate::error::Result;
+use crate::rpdb::{self, hierarchy, Molecule, Structure};
+use glm::{scaling, vec4, Mat4};
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+/// One molecule type of the scene.
+pub struct SceneMolecule {
+    pub molecule: Molecule,
+    /// Atoms of every LOD in `Scene::atoms`, counted in atoms.
+    pub lods_atoms: Vec<Range<u32>>,
+    /// Model matrices of every instance of this type.
+    pub model_matrices: Vec<Mat4>,
+}
+
+impl SceneMolecule {
+    pub fn bounding_box(&self) -> &rpdb::BoundingBox {
+        &self.molecule.bounding_box
+    }
+
+    pub fn lods_radii(&self) -> Vec<f32> {
+        self.molecule.lods().iter().map(|lod| lod.max_radius()).collect()
+    }
+
+    /// LOD ranges for draw calls emitting `vertices_per_atom` vertices per atom.
+    pub fn lods_vertices(&self, vertices_per_atom: u32) -> Vec<Range<u32>> {
+        self.lods_atoms
+            .iter()
+            .map(|range| range.start * vertices_per_atom..range.end * vertices_per_atom)
+            .collect()
+    }
+
+    /// Instance model matrices packed for a storage buffer.
+    pub fn model_matrices_packed(&self) -> Vec<f32> {
+        self.model_matrices.iter().flat_map(|m| m.as_slice().iter().copied()).collect()
+    }
+}
+
+pub struct Scene {
+    pub molecule_name_id: HashMap<String, usize>,
+    pub molecules: Vec<SceneMolecule>,
+    /// Atoms of all molecule types and LODs, four floats each.
+    pub atoms: Vec<f32>,
+}
+
+impl Scene {
+    /// Loads a structure, its nested structures and every molecule it instances.
+    pub fn load(structure_path: &Path) -> Self {
+        Self::try_load(structure_path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_load(structure_path: &Path) -> Result<Self> {
+        Self::from_structure(&Structure::try_load(structure_path)?, structure_path)
+    }
+
+    /// Builds the scene of a structure stored at `structure_path`, molecules are looked up next to it.
+    pub fn from_structure(structure: &Structure, structure_path: &Path) -> Result<Self> {
+        let resolved = hierarchy::flatten(structure, structure_path)?;
+        let structure = resolved.structure;
+
+        let mut scene = Scene {
+            molecule_name_id: HashMap::new(),
+            molecules: Vec::new(),
+            atoms: Vec::new(),
+        };
+
+        let mut atoms_sum = 0u32;
+        for (name, matrix) in structure.names.iter().zip(structure.model_matrices.iter()) {
+            if !scene.molecule_name_id.contains_key(name) {
+                let molecule = Molecule::try_load(&Structure::molecule_path(structure_path, name))?;
+
+                let mut lods_atoms = Vec::new();
+                for lod in molecule.lods() {
+                    for atom in lod.atoms() {
+                        scene.atoms.extend_from_slice(&[atom.x, atom.y, atom.z, atom.w]);
+                    }
+                    lods_atoms.push(atoms_sum..atoms_sum + lod.atoms().len() as u32);
+                    atoms_sum += lod.atoms().len() as u32;
+                }
+
+                scene.molecule_name_id.insert(name.clone(), scene.molecules.len());
+                scene.molecules.push(SceneMolecule {
+                    molecule,
+                    lods_atoms,
+                    model_matrices: Vec::new(),
+                });
+            }
+
+            scene.molecules[scene.molecule_name_id[name]].model_matrices.push(*matrix);
+        }
+
+        Ok(scene)
+    }
+
+    pub fn instances_len(&self) -> usize {
+        self.molecules.iter().map(|m| m.model_matrices.len()).sum()
+    }
+
+    /// Atoms with every atom repeated `vertices_per_atom` times, for vertex buffers of billboards.
+    pub fn atoms_per_vertex(&self, vertices_per_atom: usize) -> Vec<f32> {
+        let mut vertices = Vec::with_capacity(self.atoms.len() * vertices_per_atom);
+        for atom in self.atoms.chunks(4) {
+            for _ in 0..vertices_per_atom {
+                vertices.extend_from_slice(atom);
+            }
+        }
+
+        vertices
+    }
+
+    /// Matrices of the bounding boxes of all instances, in the order of the molecule types. The unit cube is
+    /// scaled to the size of the bounding box and transformed by the instance matrix.
+    pub fn aabb_matrices(&self) -> Vec<f32> {
+        let mut matrices = Vec::with_capacity(self.instances_len() * 16);
+        for molecule in &self.molecules {
+            let bounding_box = molecule.bounding_box();
+            let size = scaling(&(bounding_box.max - bounding_box.min));
+            for m in &molecule.model_matrices {
+                matrices.extend_from_slice((m * size).as_slice());
+            }
+        }
+
+        matrices
+    }
+
+    /// Atoms of a LOD of all instances in world space, four floats each. Molecules with fewer LODs use their
+    /// coarsest one.
+    pub fn world_atoms(&self, lod: usize) -> Vec<f32> {
+        let mut atoms = Vec::new();
+        for molecule in &self.molecules {
+            let lods = molecule.molecule.lods();
+            let lod_atoms = match lods.get(lod).or_else(|| lods.last()) {
+                Some(lod) => lod.atoms(),
+                None => continue,
+            };
+            for m in &molecule.model_matrices {
+                for atom in lod_atoms {
+                    let position = m * vec4(atom.x, atom.y, atom.z, 1.0);
+                    atoms.extend_from_slice(&[position.x, position.y, position.z, atom.w]);
+                }
+            }
+        }
+
+        atoms
+    }
+}
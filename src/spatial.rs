@@ -0,0 +1,280 @@
+use glm::{distance, distance2, vec4, Vec3, Vec4};
+use nalgebra_glm as glm;
+
+/// Static 3D k-d tree over a set of points, used for nearest-neighbour queries.
+///
+/// The tree is implicit: `nodes` is ordered so that the median of every range is the splitting node
+/// and the two halves around it are its subtrees. Nodes keep a copy of their point for cache locality.
+pub struct KdTree {
+    points: Vec<Vec3>,
+    nodes: Vec<(Vec3, u32)>,
+}
+
+impl KdTree {
+    pub fn new(points: Vec<Vec3>) -> Self {
+        let mut nodes: Vec<(Vec3, u32)> = points.iter().enumerate().map(|(i, p)| (*p, i as u32)).collect();
+        build(&mut nodes, 0);
+
+        Self { points, nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn points(&self) -> &[Vec3] {
+        &self.points
+    }
+
+    /// Index of the closest point and its squared distance. `None` for an empty tree.
+    pub fn nearest(&self, query: &Vec3) -> Option<(usize, f32)> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let mut best = (std::u32::MAX, std::f32::INFINITY);
+        self.nearest_in(query, 0, self.nodes.len(), 0, &|_| true, &mut best);
+
+        Some((best.0 as usize, best.1))
+    }
+
+    /// Closest point among those accepted by `filter` (e.g. every point but the query itself).
+    pub fn nearest_where<F: Fn(usize) -> bool>(&self, query: &Vec3, filter: F) -> Option<(usize, f32)> {
+        let mut best = (std::u32::MAX, std::f32::INFINITY);
+        self.nearest_in(query, 0, self.nodes.len(), 0, &filter, &mut best);
+
+        if best.0 == std::u32::MAX {
+            None
+        } else {
+            Some((best.0 as usize, best.1))
+        }
+    }
+
+    /// Like `nearest`, but starts from a guess (e.g. the previous answer for a point that barely moved),
+    /// which prunes most of the tree when the guess is good.
+    pub fn nearest_from(&self, query: &Vec3, guess: usize) -> Option<(usize, f32)> {
+        if guess >= self.points.len() {
+            return self.nearest(query);
+        }
+
+        let mut best = (guess as u32, distance2(query, &self.points[guess]));
+        self.nearest_in(query, 0, self.nodes.len(), 0, &|_| true, &mut best);
+
+        Some((best.0 as usize, best.1))
+    }
+
+    fn nearest_in<F: Fn(usize) -> bool>(&self, query: &Vec3, lo: usize, hi: usize, depth: usize, filter: &F, best: &mut (u32, f32)) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = (lo + hi) / 2;
+        let (point, index) = &self.nodes[mid];
+        let dist = distance2(query, point);
+        if (dist < best.1 || (dist == best.1 && *index < best.0)) && filter(*index as usize) {
+            *best = (*index, dist);
+        }
+
+        let axis = depth % 3;
+        let diff = query[axis] - point[axis];
+        let (near, far) = if diff < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+
+        self.nearest_in(query, near.0, near.1, depth + 1, filter, best);
+        if diff * diff <= best.1 {
+            self.nearest_in(query, far.0, far.1, depth + 1, filter, best);
+        }
+    }
+
+    /// Calls `f` with the index and squared distance of every point within `radius` of the query.
+    pub fn for_each_within<F: FnMut(usize, f32)>(&self, query: &Vec3, radius: f32, mut f: F) {
+        self.within_in(query, radius * radius, 0, self.nodes.len(), 0, &mut f);
+    }
+
+    fn within_in<F: FnMut(usize, f32)>(&self, query: &Vec3, radius2: f32, lo: usize, hi: usize, depth: usize, f: &mut F) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = (lo + hi) / 2;
+        let (point, index) = &self.nodes[mid];
+        let dist = distance2(query, point);
+        if dist <= radius2 {
+            f(*index as usize, dist);
+        }
+
+        let axis = depth % 3;
+        let diff = query[axis] - point[axis];
+        if diff < 0.0 || diff * diff <= radius2 {
+            self.within_in(query, radius2, lo, mid, depth + 1, f);
+        }
+        if diff >= 0.0 || diff * diff <= radius2 {
+            self.within_in(query, radius2, mid + 1, hi, depth + 1, f);
+        }
+    }
+}
+
+fn build(nodes: &mut [(Vec3, u32)], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+
+    let axis = depth % 3;
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| {
+        a.0[axis]
+            .partial_cmp(&b.0[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.1.cmp(&b.1))
+    });
+
+    let (left, right) = nodes.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+/// 30-bit Morton code of a point normalized into the `[min, max]` box, used to order points along a space-filling curve.
+pub fn morton_code(point: &Vec3, min: &Vec3, max: &Vec3) -> u32 {
+    fn spread(v: u32) -> u32 {
+        let v = (v | (v << 16)) & 0x030000FF;
+        let v = (v | (v << 8)) & 0x0300F00F;
+        let v = (v | (v << 4)) & 0x030C30C3;
+        (v | (v << 2)) & 0x09249249
+    }
+
+    let extent = max - min;
+    let mut code = 0;
+    for axis in 0..3 {
+        let normalized = if extent[axis] > 0.0 {
+            (point[axis] - min[axis]) / extent[axis]
+        } else {
+            0.0
+        };
+        let quantized = (normalized * 1023.0).max(0.0).min(1023.0) as u32;
+        code |= spread(quantized) << (2 - axis);
+    }
+
+    code
+}
+
+/// Radius around `center` that encloses every `xyzr` sphere.
+pub fn enclosing_radius(center: &Vec3, spheres: &[Vec4]) -> f32 {
+    spheres
+        .iter()
+        .fold(0.0f32, |radius, s| radius.max(distance(center, &s.xyz()) + s.w))
+}
+
+/// Sphere centered at the centroid of the sphere centers that encloses all the spheres.
+pub fn tight_enclosing_sphere(spheres: &[Vec4]) -> Vec4 {
+    let mut center: Vec3 = glm::zero();
+    for sphere in spheres {
+        center += sphere.xyz();
+    }
+    center /= spheres.len().max(1) as f32;
+
+    vec4(center.x, center.y, center.z, enclosing_radius(&center, spheres))
+}
+
+/// Approximation of the smallest sphere enclosing all the spheres (Badoiu-Clarkson iterations).
+///
+/// The center is only approximate, the radius is always computed exactly for it, so containment holds
+/// and the result is never larger than `tight_enclosing_sphere`.
+pub fn minimal_enclosing_sphere(spheres: &[Vec4]) -> Vec4 {
+    const ITERATIONS: usize = 64;
+
+    let tight = tight_enclosing_sphere(spheres);
+    if spheres.len() <= 1 {
+        return tight;
+    }
+
+    let mut center = tight.xyz();
+    for i in 1..=ITERATIONS {
+        // Farthest point of the union from the current center
+        let mut farthest_point = center;
+        let mut farthest_distance = -1.0f32;
+        for sphere in spheres {
+            let offset = sphere.xyz() - center;
+            let length = glm::length(&offset);
+            if length + sphere.w > farthest_distance {
+                farthest_distance = length + sphere.w;
+                farthest_point = if length > 0.0 {
+                    sphere.xyz() + offset * (sphere.w / length)
+                } else {
+                    sphere.xyz() + glm::vec3(sphere.w, 0.0, 0.0)
+                };
+            }
+        }
+
+        center += (farthest_point - center) / (i + 1) as f32;
+    }
+
+    let radius = enclosing_radius(&center, spheres);
+    if radius < tight.w {
+        vec4(center.x, center.y, center.z, radius)
+    } else {
+        tight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_encloses(sphere: &Vec4, members: &[Vec4]) {
+        for member in members {
+            let reach = distance(&sphere.xyz(), &member.xyz()) + member.w;
+            assert!(reach <= sphere.w * (1.0 + 1.0e-5), "{:?} sticks out of {:?}", member, sphere);
+        }
+    }
+
+    #[test]
+    fn minimal_sphere_of_a_single_sphere_is_the_sphere() {
+        let sphere = vec4(1.0, -2.0, 3.0, 1.5);
+        assert_eq!(minimal_enclosing_sphere(&[sphere]), sphere);
+    }
+
+    #[test]
+    fn minimal_sphere_of_two_spheres_spans_them() {
+        let spheres = [vec4(-5.0, 0.0, 0.0, 1.0), vec4(5.0, 0.0, 0.0, 3.0)];
+        let sphere = minimal_enclosing_sphere(&spheres);
+
+        // Exact answer is centered at x = 1 with radius 7, the centroid sphere has radius 8
+        assert_encloses(&sphere, &spheres);
+        assert!(sphere.w <= 7.0 * 1.01, "{:?}", sphere);
+        assert!(distance(&sphere.xyz(), &glm::vec3(1.0, 0.0, 0.0)) < 0.07, "{:?}", sphere);
+    }
+
+    #[test]
+    fn minimal_sphere_of_a_regular_tetrahedron_is_its_circumsphere() {
+        let s = 1.0 / 3.0f32.sqrt();
+        let spheres = [vec4(s, s, s, 0.5), vec4(s, -s, -s, 0.5), vec4(-s, s, -s, 0.5), vec4(-s, -s, s, 0.5)];
+        let sphere = minimal_enclosing_sphere(&spheres);
+
+        assert_encloses(&sphere, &spheres);
+        assert!((sphere.w - 1.5).abs() < 1.0e-3, "{:?}", sphere);
+        assert!(glm::length(&sphere.xyz()) < 1.0e-3, "{:?}", sphere);
+    }
+
+    #[test]
+    fn minimal_sphere_is_never_larger_than_the_tight_one() {
+        let spheres: Vec<Vec4> = (0..100)
+            .map(|i| {
+                let t = i as f32 * 0.37;
+                vec4(t.sin() * 10.0, (t * 1.3).cos() * 4.0, t * 0.2, 1.0 + (t * 2.1).sin().abs())
+            })
+            .collect();
+        let minimal = minimal_enclosing_sphere(&spheres);
+        let tight = tight_enclosing_sphere(&spheres);
+
+        assert_encloses(&minimal, &spheres);
+        assert_encloses(&tight, &spheres);
+        assert!(minimal.w <= tight.w);
+    }
+}
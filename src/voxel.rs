@@ -0,0 +1,1091 @@
+//! Voxelization of sphere sets and occluders extracted from the voxels.
+//!
+//! The grid is a cube of `size`³ voxels stretched over the bounding box of the spheres, so voxels are not
+//! necessarily cubic. Voxel coordinates are `i32` vectors, the flat index is `x + size * (y + size * z)`.
+//! Voxels are stored one bit each (`Voxels`), 512³ voxels take 16 MB.
+use crate::contour;
+use bytemuck::*;
+use glm::{vec2, vec3, vec4, Vec3, Vec4};
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+
+pub mod distance;
+pub mod occlusion;
+pub mod surface;
+
+/// How a voxel is tested against a sphere.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sampling {
+    /// A voxel is solid when its center is inside a sphere.
+    Center,
+    /// A voxel is solid when all its corners are inside one sphere, so solid voxels never stick out of
+    /// the spheres. Conservative, which is what occluders need.
+    Corners,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VoxelizationOptions {
+    /// Number of voxels along every axis.
+    pub size: i32,
+    pub sampling: Sampling,
+    /// Fills the empty regions that are not connected to the border of the grid.
+    pub fill_interior: bool,
+}
+
+impl Default for VoxelizationOptions {
+    fn default() -> Self {
+        Self {
+            size: 512,
+            sampling: Sampling::Corners,
+            fill_interior: true,
+        }
+    }
+}
+
+/// How the slices of the planar occluders are turned into polygons.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SliceContours {
+    /// Staircase outlines of the solid voxels in the slice, simplified afterwards.
+    Voxels,
+    /// Iso-contours of the signed distance field with marching squares, which follow the surface of the voxels
+    /// below the pixel size.
+    Distance,
+}
+
+// Sets the bits `first..=last` of a row of words
+fn set_bits(row: &mut [u64], first: usize, last: usize) {
+    let (first_word, last_word) = (first / 64, last / 64);
+    for (word, bits) in row.iter_mut().enumerate().take(last_word + 1).skip(first_word) {
+        let low = if word == first_word { first % 64 } else { 0 };
+        let high = if word == last_word { last % 64 } else { 63 };
+
+        *bits |= (std::u64::MAX >> (63 - high)) & (std::u64::MAX << low);
+    }
+}
+
+/// Box of voxels given by its minimum and maximum voxel, both inclusive.
+pub type VoxelBox = (glm::TVec3<i32>, glm::TVec3<i32>);
+
+/// Number of voxels in a box.
+pub fn box_volume(voxel_box: &VoxelBox) -> usize {
+    let extent = voxel_box.1 - voxel_box.0;
+    (extent.x + 1) as usize * (extent.y + 1) as usize * (extent.z + 1) as usize
+}
+
+fn box_contains(voxel_box: &VoxelBox, position: &glm::TVec3<i32>) -> bool {
+    (0..3).all(|axis| voxel_box.0[axis] <= position[axis] && position[axis] <= voxel_box.1[axis])
+}
+
+fn boxes_overlap(a: &VoxelBox, b: &VoxelBox) -> bool {
+    (0..3).all(|axis| a.0[axis] <= b.1[axis] && b.0[axis] <= a.1[axis])
+}
+
+// Box occluder candidates are grown from this many seeds per requested occluder
+const SEEDS_PER_OCCLUDER: usize = 16;
+const MIN_OCCLUDER_SEEDS: usize = 256;
+
+// Number of z-slices voxelized by one task
+const SLAB_SLICES: i32 = 8;
+
+/// Cube of `size`³ bits. Rows along x are padded to whole words, so every z-slice is a contiguous range of
+/// words and slices can be written in parallel.
+#[derive(Clone)]
+pub struct Voxels {
+    size: usize,
+    words_per_row: usize,
+    words: Vec<u64>,
+}
+
+impl Voxels {
+    pub fn new(size: usize) -> Self {
+        let words_per_row = (size + 63) / 64;
+
+        Self {
+            size,
+            words_per_row,
+            words: vec![0; size * size * words_per_row],
+        }
+    }
+
+    /// Number of voxels.
+    pub fn len(&self) -> usize {
+        self.size * self.size * self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    // Word and bit of a flat voxel index
+    fn locate(&self, index: usize) -> (usize, u64) {
+        let row = index / self.size;
+        let x = index % self.size;
+
+        (row * self.words_per_row + x / 64, 1 << (x % 64))
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        let (word, bit) = self.locate(index);
+        self.words[word] & bit != 0
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        let (word, bit) = self.locate(index);
+        if value {
+            self.words[word] |= bit;
+        } else {
+            self.words[word] &= !bit;
+        }
+    }
+
+    /// Number of solid voxels.
+    pub fn count(&self) -> usize {
+        self.words.par_iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Size of the storage in bytes.
+    pub fn memory(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+    }
+
+    /// Swaps solid and empty voxels.
+    pub fn invert(&mut self) {
+        let padding = self.words_per_row * 64 - self.size;
+        let last_word_mask = std::u64::MAX >> padding;
+        let words_per_row = self.words_per_row;
+
+        self.words.par_chunks_mut(words_per_row.max(1)).for_each(|row| {
+            for word in row.iter_mut() {
+                *word = !*word;
+            }
+            if let Some(last) = row.last_mut() {
+                *last &= last_word_mask;
+            }
+        });
+    }
+
+    fn slice_words(&self) -> usize {
+        self.size * self.words_per_row
+    }
+}
+
+pub struct VoxelGrid {
+    //
+    pub size: i32,
+
+    // Bounding box of the grid in world space
+    pub bb_min: Vec3,
+    pub bb_max: Vec3,
+    pub bb_diff: Vec3,
+
+    // Sizes related to single voxel
+    pub voxel_size: Vec3,
+    pub voxel_halfsize: Vec3,
+    pub voxel_diameter: f32,
+
+    pub voxels: Voxels,
+}
+#[derive(Copy, Clone)]
+pub enum Round {
+    Floor,
+    Ceil,
+}
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ClipPlane {
+    pub point: [f32; 4],
+    pub normal: [f32; 4],
+}
+
+impl Default for ClipPlane {
+    fn default() -> Self {
+        Self {
+            point: [0.0; 4],
+            normal: [0.0; 4],
+        }
+    }
+}
+
+unsafe impl Zeroable for ClipPlane {}
+unsafe impl Pod for ClipPlane {}
+
+impl std::fmt::Display for VoxelGrid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}³ voxels of {:.2} x {:.2} x {:.2} Å, {} solid, {:.1} MB",
+            self.size,
+            self.voxel_size.x,
+            self.voxel_size.y,
+            self.voxel_size.z,
+            self.solid_count(),
+            self.memory() as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+impl VoxelGrid {
+    // World position inside BB -> voxel space
+    pub fn snap(&self, input: Vec3, round: Round) -> glm::TVec3<i32> {
+        let grid_position = input - self.bb_min;
+
+        let grid_position = vec3(
+            grid_position.x / self.voxel_size.x,
+            grid_position.y / self.voxel_size.y,
+            grid_position.z / self.voxel_size.z,
+        );
+
+        let grid_position = match round {
+            Round::Floor => grid_position.apply_into(|e| e.floor()),
+            Round::Ceil => grid_position.apply_into(|e| e.ceil()),
+        };
+
+        vec3(grid_position.x as i32, grid_position.y as i32, grid_position.z as i32)
+    }
+
+    // Voxel space -> middle of voxel in world space
+    pub fn to_ws(&self, input: glm::TVec3<i32>) -> Vec3 {
+        let input_f32 = vec3(input.x as f32, input.y as f32, input.z as f32);
+        let voxel_center = vec3(
+            input_f32.x * self.voxel_size.x + self.voxel_halfsize.x,
+            input_f32.y * self.voxel_size.y + self.voxel_halfsize.y,
+            input_f32.z * self.voxel_size.z + self.voxel_halfsize.z,
+        );
+
+        voxel_center + self.bb_min
+    }
+
+    pub fn to_1d(&self, input: glm::TVec3<i32>) -> usize {
+        let width = self.size as usize;
+        let height = self.size as usize;
+        let x = input.x as usize;
+        let y = input.y as usize;
+        let z = input.z as usize;
+
+        (width * height * z) + (width * y) + x
+    }
+
+    pub fn to_3d(&self, input: usize) -> glm::TVec3<i32> {
+        let width = self.size as usize;
+        let height = self.size as usize;
+        let slice = width * height;
+
+        let z = input / slice;
+        let y = (input % slice) / width;
+        let x = (input % slice) % width;
+
+        vec3(x as i32, y as i32, z as i32)
+    }
+
+    pub fn contains(&self, input: glm::TVec3<i32>) -> bool {
+        input.x >= 0 && input.y >= 0 && input.z >= 0 && input.x < self.size && input.y < self.size && input.z < self.size
+    }
+
+    pub fn is_solid(&self, input: glm::TVec3<i32>) -> bool {
+        self.contains(input) && self.voxels.get(self.to_1d(input))
+    }
+
+    pub fn solid_count(&self) -> usize {
+        self.voxels.count()
+    }
+
+    /// Size of the voxel storage in bytes.
+    pub fn memory(&self) -> usize {
+        self.voxels.memory()
+    }
+
+    /// Voxelizes spheres (`xyz` center, `w` radius). The spheres are moved so that their bounding box is
+    /// centered at the origin.
+    pub fn new(atoms: &mut [Vec4], options: &VoxelizationOptions) -> Self {
+        assert!(options.size > 0);
+
+        // Find bounding box of the entire structure
+        let infinity = vec3(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+        let (mut bb_min, mut bb_max) = atoms
+            .par_iter()
+            .map(|atom| {
+                let atom_radius = vec3(atom.w, atom.w, atom.w);
+                (atom.xyz() - atom_radius, atom.xyz() + atom_radius)
+            })
+            .reduce(|| (infinity, -infinity), |a, b| (glm::min2(&a.0, &b.0), glm::max2(&a.1, &b.1)));
+
+        // Center the molecules (+their bounding box)
+        let bb_center = (bb_max + bb_min) / 2.0;
+        bb_max -= bb_center;
+        bb_min -= bb_center;
+        atoms.par_iter_mut().for_each(|atom| {
+            atom.x -= bb_center.x;
+            atom.y -= bb_center.y;
+            atom.z -= bb_center.z;
+        });
+
+        let bb_diff = bb_max - bb_min;
+
+        // Create voxel grid
+        let size = options.size;
+
+        let voxel_size = vec3(bb_diff.x / size as f32, bb_diff.y / size as f32, bb_diff.z / size as f32);
+        let voxel_halfsize = voxel_size.apply_into(|e| e * 0.5);
+        let voxel_diameter = glm::distance(&voxel_size, &glm::vec3(0.0, 0.0, 0.0));
+
+        let mut grid = Self {
+            size,
+
+            bb_min,
+            bb_max,
+            bb_diff,
+
+            voxel_size,
+            voxel_halfsize,
+            voxel_diameter,
+
+            voxels: Voxels::new(size as usize),
+        };
+
+        // A voxel is inside a sphere when its center, or its corner farthest from the sphere center, is inside
+        let margin = match options.sampling {
+            Sampling::Center => glm::zero(),
+            Sampling::Corners => voxel_halfsize,
+        };
+
+        // Voxel bounding box of every atom, clamped to the grid
+        let atoms_bounds: Vec<(glm::TVec3<i32>, glm::TVec3<i32>)> = atoms
+            .par_iter()
+            .map(|atom| {
+                let atom_radius = vec3(atom.w, atom.w, atom.w);
+                let atom_bb_max = grid.snap(atom.xyz() + atom_radius, Round::Ceil);
+                let atom_bb_min = grid.snap(atom.xyz() - atom_radius, Round::Floor);
+
+                (glm::max(&atom_bb_min, 0), glm::min(&atom_bb_max, grid.size))
+            })
+            .collect();
+
+        // Atoms are binned into slabs of z-slices, every slab is voxelized by one task
+        let slabs_num = (size + SLAB_SLICES - 1) / SLAB_SLICES;
+        let mut slabs_atoms = vec![Vec::new(); slabs_num as usize];
+        for (atom_index, (atom_bb_min, atom_bb_max)) in atoms_bounds.iter().enumerate() {
+            if atom_bb_min.x >= atom_bb_max.x || atom_bb_min.y >= atom_bb_max.y || atom_bb_min.z >= atom_bb_max.z {
+                continue;
+            }
+
+            for slab in atom_bb_min.z / SLAB_SLICES..=(atom_bb_max.z - 1) / SLAB_SLICES {
+                slabs_atoms[slab as usize].push(atom_index as u32);
+            }
+        }
+
+        let mut words = std::mem::take(&mut grid.voxels.words);
+        let slice_words = grid.voxels.slice_words();
+        let words_per_row = grid.voxels.words_per_row;
+        words
+            .par_chunks_mut(SLAB_SLICES as usize * slice_words)
+            .zip(slabs_atoms.par_iter())
+            .enumerate()
+            .for_each(|(slab, (slab_words, slab_atoms))| {
+                let slab_min = slab as i32 * SLAB_SLICES;
+                let slab_max = slab_min + SLAB_SLICES;
+
+                for atom_index in slab_atoms {
+                    let atom = &atoms[*atom_index as usize];
+                    let atom_position = atom.xyz();
+                    let atom_radius = atom.w;
+                    let (atom_bb_min, atom_bb_max) = atoms_bounds[*atom_index as usize];
+
+                    for z in atom_bb_min.z.max(slab_min)..atom_bb_max.z.min(slab_max) {
+                        for y in atom_bb_min.y..atom_bb_max.y {
+                            let voxel_center = grid.to_ws(vec3(atom_bb_min.x, y, z));
+                            let dy = (voxel_center.y - atom_position.y).abs() + margin.y;
+                            let dz = (voxel_center.z - atom_position.z).abs() + margin.z;
+
+                            // Half of the span of voxel centers along x inside the sphere
+                            let remaining = atom_radius * atom_radius - dy * dy - dz * dz;
+                            if remaining < 0.0 {
+                                continue;
+                            }
+                            let half_span = remaining.sqrt() - margin.x;
+                            if half_span < 0.0 {
+                                continue;
+                            }
+
+                            let to_voxel = |x: f32| (x - grid.bb_min.x) / voxel_size.x - 0.5;
+                            let x_min = (to_voxel(atom_position.x - half_span).ceil() as i32).max(atom_bb_min.x);
+                            let x_max = (to_voxel(atom_position.x + half_span).floor() as i32).min(atom_bb_max.x - 1);
+
+                            let row = ((z - slab_min) as usize * grid.size as usize + y as usize) * words_per_row;
+                            if x_min <= x_max {
+                                set_bits(&mut slab_words[row..row + words_per_row], x_min as usize, x_max as usize);
+                            }
+                        }
+                    }
+                }
+            });
+        grid.voxels.words = words;
+
+        if options.fill_interior {
+            grid.fill_interior();
+        }
+
+        grid
+    }
+
+    /// Fills the empty voxels that cannot be reached from the border of the grid through face neighbours.
+    pub fn fill_interior(&mut self) {
+        let size = self.size as usize;
+        if size == 0 {
+            return;
+        }
+        let last = size - 1;
+        let words_per_row = self.voxels.words_per_row;
+        let row_start = |y: usize, z: usize| (z * size + y) * words_per_row;
+
+        // Scanline flood fill from the empty voxels on the border. Seeds are (y, z, x), every seed grows into
+        // the whole run of empty voxels along x and seeds the runs next to it.
+        let mut outside = Voxels::new(size);
+        let mut seeds = Vec::new();
+        for z in 0..size {
+            for y in 0..size {
+                if z == 0 || z == last || y == 0 || y == last {
+                    seeds.extend((0..size).map(|x| (y, z, x)));
+                } else {
+                    seeds.push((y, z, 0));
+                    seeds.push((y, z, last));
+                }
+            }
+        }
+
+        let solid = &self.voxels.words;
+        let is_open = |outside: &[u64], row: usize, x: usize| {
+            let word = row + x / 64;
+            ((solid[word] | outside[word]) >> (x % 64)) & 1 == 0
+        };
+        while let Some((y, z, x)) = seeds.pop() {
+            let row = row_start(y, z);
+            if !is_open(&outside.words, row, x) {
+                continue;
+            }
+
+            let mut first = x;
+            while first > 0 && is_open(&outside.words, row, first - 1) {
+                first -= 1;
+            }
+            let mut last_x = x;
+            while last_x < last && is_open(&outside.words, row, last_x + 1) {
+                last_x += 1;
+            }
+            set_bits(&mut outside.words[row..row + words_per_row], first, last_x);
+
+            let neighbours = [(y.wrapping_sub(1), z), (y + 1, z), (y, z.wrapping_sub(1)), (y, z + 1)];
+            for &(ny, nz) in &neighbours {
+                if ny >= size || nz >= size {
+                    continue;
+                }
+
+                // One seed per run of open voxels next to this run
+                let neighbour_row = row_start(ny, nz);
+                let mut in_run = false;
+                for x in first..=last_x {
+                    let open = is_open(&outside.words, neighbour_row, x);
+                    if open && !in_run {
+                        seeds.push((ny, nz, x));
+                    }
+                    in_run = open;
+                }
+            }
+        }
+
+        outside.invert();
+        self.voxels = outside;
+    }
+
+    /// Corners of a box of voxels in world space.
+    pub fn box_to_ws(&self, voxel_box: &VoxelBox) -> (Vec3, Vec3) {
+        (
+            self.to_ws(voxel_box.0) - self.voxel_halfsize,
+            self.to_ws(voxel_box.1) + self.voxel_halfsize,
+        )
+    }
+
+    fn is_box_solid(&self, voxels: &Voxels, voxel_box: &VoxelBox) -> bool {
+        let (min, max) = voxel_box;
+        (min.z..=max.z).all(|z| (min.y..=max.y).all(|y| (min.x..=max.x).all(|x| voxels.get(self.to_1d(vec3(x, y, z))))))
+    }
+
+    // Grows a box from a solid seed while the layer next to one of its faces is solid, the largest layer first
+    fn grow_box(&self, voxels: &Voxels, seed: glm::TVec3<i32>) -> VoxelBox {
+        let (mut min, mut max) = (seed, seed);
+
+        loop {
+            let mut best: Option<(usize, usize, i32)> = None;
+            for axis in 0..3 {
+                for &coordinate in &[min[axis] - 1, max[axis] + 1] {
+                    if coordinate < 0 || coordinate >= self.size {
+                        continue;
+                    }
+
+                    let mut layer = (min, max);
+                    layer.0[axis] = coordinate;
+                    layer.1[axis] = coordinate;
+                    let area = box_volume(&layer);
+
+                    if best.map_or(true, |(best_area, _, _)| area > best_area) && self.is_box_solid(voxels, &layer) {
+                        best = Some((area, axis, coordinate));
+                    }
+                }
+            }
+
+            match best {
+                Some((_, axis, coordinate)) if coordinate < min[axis] => min[axis] = coordinate,
+                Some((_, axis, coordinate)) => max[axis] = coordinate,
+                None => return (min, max),
+            }
+        }
+    }
+
+    /// Non-overlapping boxes of solid voxels in decreasing volume, at most `max_count` of them and none smaller
+    /// than `min_volume` voxels.
+    ///
+    /// Candidates are maximal boxes grown from the local maxima of the distance to the empty voxels, the deepest
+    /// ones first. The largest candidate is taken, its voxels are carved out and the candidates it overlaps are
+    /// grown again from their seeds. Once the candidates run out, new seeds are searched in the remaining voxels.
+    pub fn get_box_occluders(&self, max_count: usize, min_volume: usize) -> Vec<VoxelBox> {
+        let mut remaining = self.voxels.clone();
+
+        // Squared distance of the remaining solid voxels to the empty ones, lowered around every carved box
+        let mut depth: Vec<f32> = (0..remaining.len())
+            .into_par_iter()
+            .map(|i| if remaining.get(i) { std::f32::INFINITY } else { 0.0 })
+            .collect();
+        distance::squared_distance_transform(&mut depth, self.size as usize, self.voxel_size);
+        // Depths stay infinite without any empty voxel, no depth reaches beyond the grid diagonal anyway
+        let reach = depth
+            .par_iter()
+            .cloned()
+            .reduce(|| 0.0, f32::max)
+            .sqrt()
+            .min(glm::length(&self.bb_diff));
+
+        let mut occluders: Vec<VoxelBox> = Vec::new();
+        while occluders.len() < max_count
+            && self.carve_box_occluders(&mut remaining, &mut depth, reach, &mut occluders, max_count, min_volume)
+        {}
+
+        // A box grown again can be larger than the one taken before it
+        occluders.sort_by_key(|occluder| std::cmp::Reverse(box_volume(occluder)));
+        occluders
+    }
+
+    // Carved voxels become empty. Only the depths within `reach` (the largest depth) of the box can drop, to the
+    // distance to the box itself, as the distance to a union of empty voxels is the smallest of the distances.
+    fn carve_depth(&self, depth: &mut [f32], voxel_box: &VoxelBox, reach: f32) {
+        let size = self.size as usize;
+        let margin = vec3(
+            (reach / self.voxel_size.x).ceil() as i32,
+            (reach / self.voxel_size.y).ceil() as i32,
+            (reach / self.voxel_size.z).ceil() as i32,
+        );
+        let min = glm::max(&(voxel_box.0 - margin), 0);
+        let max = glm::min(&(voxel_box.1 + margin), self.size - 1);
+
+        let gap = |axis: usize, coordinate: i32| {
+            let voxels = (voxel_box.0[axis] - coordinate).max(coordinate - voxel_box.1[axis]).max(0);
+            let gap = voxels as f32 * self.voxel_size[axis];
+            gap * gap
+        };
+
+        depth
+            .par_chunks_mut(size * size)
+            .enumerate()
+            .skip(min.z as usize)
+            .take((max.z - min.z + 1) as usize)
+            .for_each(|(z, slice)| {
+                let gap_z = gap(2, z as i32);
+                for y in min.y..=max.y {
+                    let gap_yz = gap(1, y) + gap_z;
+                    for x in min.x..=max.x {
+                        let value = &mut slice[y as usize * size + x as usize];
+                        *value = value.min(gap(0, x) + gap_yz);
+                    }
+                }
+            });
+    }
+
+    // One round of the box search on the remaining voxels and their depths, returns whether any box was found
+    fn carve_box_occluders(
+        &self,
+        remaining: &mut Voxels,
+        depth: &mut [f32],
+        reach: f32,
+        occluders: &mut Vec<VoxelBox>,
+        max_count: usize,
+        min_volume: usize,
+    ) -> bool {
+        use std::cmp::Reverse;
+        use std::collections::BinaryHeap;
+
+        if remaining.is_empty() {
+            return false;
+        }
+
+        // Seeds are the local maxima of the depth, deepest first
+        let neighbours = [
+            vec3(1, 0, 0),
+            vec3(-1, 0, 0),
+            vec3(0, 1, 0),
+            vec3(0, -1, 0),
+            vec3(0, 0, 1),
+            vec3(0, 0, -1),
+        ];
+        let mut seeds: Vec<usize> = (0..remaining.len())
+            .into_par_iter()
+            .filter(|&i| {
+                let position = self.to_3d(i);
+                remaining.get(i)
+                    && neighbours
+                        .iter()
+                        .map(|offset| position + offset)
+                        .filter(|neighbour| self.contains(*neighbour))
+                        .all(|neighbour| depth[self.to_1d(neighbour)] <= depth[i])
+            })
+            .collect();
+        seeds.par_sort_by(|a, b| depth[*b].partial_cmp(&depth[*a]).unwrap().then(a.cmp(b)));
+        seeds.truncate(std::cmp::max(
+            MIN_OCCLUDER_SEEDS,
+            SEEDS_PER_OCCLUDER * (max_count - occluders.len()),
+        ));
+
+        // Seeds inside the box of a deeper seed would grow the same box
+        let mut candidates: Vec<(glm::TVec3<i32>, VoxelBox)> = Vec::new();
+        for seed in seeds {
+            let seed = self.to_3d(seed);
+            if !candidates.iter().any(|(_, candidate)| box_contains(candidate, &seed)) {
+                candidates.push((seed, self.grow_box(remaining, seed)));
+            }
+        }
+
+        // Volume, candidate and the number of occluders when it was grown
+        let first = occluders.len();
+        let mut queue: BinaryHeap<(usize, Reverse<usize>, usize)> = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, (_, candidate))| (box_volume(candidate), Reverse(i), first))
+            .collect();
+
+        while let Some((volume, Reverse(i), grown_at)) = queue.pop() {
+            if occluders.len() >= max_count || volume < min_volume.max(1) {
+                break;
+            }
+
+            let (seed, candidate) = candidates[i];
+            if occluders[grown_at..].iter().any(|occluder| boxes_overlap(occluder, &candidate)) {
+                if remaining.get(self.to_1d(seed)) {
+                    let candidate = self.grow_box(remaining, seed);
+                    candidates[i].1 = candidate;
+                    queue.push((box_volume(&candidate), Reverse(i), occluders.len()));
+                }
+                continue;
+            }
+
+            for z in candidate.0.z..=candidate.1.z {
+                for y in candidate.0.y..=candidate.1.y {
+                    for x in candidate.0.x..=candidate.1.x {
+                        remaining.set(self.to_1d(vec3(x, y, z)), false);
+                    }
+                }
+            }
+            self.carve_depth(depth, &candidate, reach);
+            occluders.push(candidate);
+        }
+
+        occluders.len() > first
+    }
+
+    pub fn get_planar_occluders(&mut self, limit: usize) -> Vec<Vec4> {
+        self.get_planar_occluders_with(limit, SliceContours::Voxels)
+    }
+
+    /// Planar occluders with a choice of how the slices are outlined, `get_planar_occluders` uses the voxel outlines.
+    pub fn get_planar_occluders_with(&mut self, limit: usize, contours: SliceContours) -> Vec<Vec4> {
+        use lyon::math::Point;
+        use lyon::tessellation::*;
+
+        let no_cuts = 32;
+        let cut_step = glm::length(&self.bb_diff) / no_cuts as f32;
+        let step = glm::length(&self.bb_diff) / self.size as f32;
+
+        let mut views = Vec::new();
+        for x in (0..180).step_by(45) {
+            for y in (0..180).step_by(45) {
+                views.push(glm::vec2((x as f32).to_radians(), (y as f32).to_radians()));
+            }
+        }
+
+        let distance_field = match contours {
+            SliceContours::Voxels => None,
+            SliceContours::Distance => Some(self.distance_transform()),
+        };
+
+        // Pixel offset of the cut images, also for odd sizes
+        let half = self.size / 2;
+
+        let mut planes_triangles: Vec<Vec<Vec4>> = Vec::new();
+        let plane_vec = vec4(0.0, 0.0, 1.0, 1.0);
+        for view in &views {
+            let rotation = glm::rotate_y(&glm::one(), view.x) * glm::rotate_x(&glm::one(), view.y);
+            let plane_vec = rotation * plane_vec;
+
+            // World-space coordinates of the center of a pixel of a cut, pixels are centered on the plane origin
+            let pixel_ws = |x: i32, y: i32, z_ws: f32| {
+                let x_ws = x as f32 * step + step * 0.5;
+                let y_ws = y as f32 * step + step * 0.5;
+
+                rotation * vec4(x_ws, y_ws, 0.0, 1.0) + plane_vec * z_ws
+            };
+
+            let mut max_cut = 0.0;
+            let mut max_area = 0;
+            let mut max_img = Vec::new();
+
+            for cut in -no_cuts / 2..=no_cuts / 2 {
+                let mut img = Vec::new();
+                let mut area: u64 = 0;
+                for y in 0..self.size {
+                    for x in 0..self.size {
+                        let ws = pixel_ws(x - half, y - half, cut as f32 * cut_step);
+
+                        let occupied;
+                        if ws.x <= self.bb_min.x
+                            || ws.x >= self.bb_max.x
+                            || ws.y <= self.bb_min.y
+                            || ws.y >= self.bb_max.y
+                            || ws.z <= self.bb_min.z
+                            || ws.z >= self.bb_max.z
+                        {
+                            occupied = 0;
+                        } else {
+                            occupied = if self.voxels.get(self.to_1d(self.snap(ws.xyz(), Round::Floor))) {
+                                1
+                            } else {
+                                0
+                            } as i8;
+                            area += occupied as u64;
+                        }
+
+                        img.push(occupied);
+                    }
+                }
+
+                if area > max_area {
+                    max_area = area;
+                    max_img = img;
+                    max_cut = cut as f32 * cut_step;
+                }
+            }
+
+            // No cut of this view hits a solid voxel
+            if max_area == 0 {
+                continue;
+            }
+
+            let mut triangles = Vec::new();
+
+            let size = self.size as usize;
+            let polygons = match &distance_field {
+                Some(distance_field) => {
+                    // Same pixels as the cut, the iso-line at zero is the surface of the voxels
+                    let distances: Vec<f32> = (0..size * size)
+                        .map(|i| {
+                            let ws = pixel_ws((i % size) as i32 - half, (i / size) as i32 - half, max_cut);
+                            self.sample_distance(distance_field, ws.xyz())
+                        })
+                        .collect();
+
+                    let tolerance = 0.5 * self.voxel_size.max() / step;
+                    contour::iso_contours(size, size, |x, y| distances[y * size + x], 0.0, Some(tolerance))
+                }
+                None => {
+                    let tolerance = 2.0 * self.voxel_size.max() / step;
+                    contour::trace_contours(size, size, |x, y| max_img[y * size + x] != 0, Some(tolerance))
+                }
+            };
+            let mut builder = lyon::path::Builder::new();
+            for polygon in polygons {
+                let sub = vec2(half as f32, half as f32);
+                let points: Vec<Point> = polygon
+                    .points
+                    .iter()
+                    .map(|point| {
+                        // Pixel corners are at integer coordinates, the pixels were sampled at their centers
+                        let point = (point - sub) * step;
+                        Point::new(point.x, point.y)
+                    })
+                    .collect();
+                builder.polygon(&points);
+            }
+            let final_path = builder.build();
+
+            // Triangulate
+            // Create a simple path.
+
+            // Will contain the result of the tessellation.
+            let mut geometry: VertexBuffers<glm::Vec2, i32> = VertexBuffers::new();
+            let mut tessellator = FillTessellator::new();
+            {
+                // Compute the tessellation.
+                tessellator
+                    .tessellate_path(
+                        &final_path,
+                        &FillOptions::default(),
+                        &mut BuffersBuilder::new(&mut geometry, |pos: Point, _: FillAttributes| glm::vec2(pos.x, pos.y)),
+                    )
+                    .unwrap();
+            }
+
+            // Remove degenerate triangles
+            // Deindex
+            // Scale to world space
+            // Align on the original plane
+            // Sort by area
+            for i in 0..geometry.indices.len() / 3 {
+                let p1 = vec4(
+                    geometry.vertices[geometry.indices[i * 3] as usize].x,
+                    geometry.vertices[geometry.indices[i * 3] as usize].y,
+                    0.0,
+                    1.0,
+                );
+                let p2 = vec4(
+                    geometry.vertices[geometry.indices[i * 3 + 1] as usize].x,
+                    geometry.vertices[geometry.indices[i * 3 + 1] as usize].y,
+                    0.0,
+                    1.0,
+                );
+                let p3 = vec4(
+                    geometry.vertices[geometry.indices[i * 3 + 2] as usize].x,
+                    geometry.vertices[geometry.indices[i * 3 + 2] as usize].y,
+                    0.0,
+                    1.0,
+                );
+
+                let z_offset = plane_vec * max_cut;
+                let p1 = rotation * p1 + z_offset;
+                let p2 = rotation * p2 + z_offset;
+                let p3 = rotation * p3 + z_offset;
+
+                let a = glm::distance(&p1, &p2);
+                let b = glm::distance(&p2, &p3);
+                let c = glm::distance(&p3, &p1);
+
+                let s = (a + b + c) / 2.0;
+                let area = s * (s - a) * (s - b) * (s - c);
+
+                if area > 0.0 {
+                    triangles.push(vec![p1, p2, p3]);
+                }
+            }
+
+            planes_triangles.push(triangles.into_iter().flatten().collect());
+        }
+
+        // Keep the triangles hiding the most of the molecule in views from all around it
+        let triangles: Vec<Vec3> = planes_triangles.into_iter().flatten().map(|vertex| vertex.xyz()).collect();
+        let evaluator = occlusion::OcclusionEvaluator::new(self, &occlusion::OcclusionOptions::default());
+
+        evaluator
+            .select_triangles(&triangles, limit)
+            .into_iter()
+            .flat_map(|triangle| triangles[triangle * 3..triangle * 3 + 3].iter())
+            .map(|vertex| vec4(vertex.x, vertex.y, vertex.z, 1.0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voxelize(atoms: &[Vec4], size: i32, sampling: Sampling, fill_interior: bool) -> VoxelGrid {
+        let mut atoms = atoms.to_vec();
+        VoxelGrid::new(
+            &mut atoms,
+            &VoxelizationOptions {
+                size,
+                sampling,
+                fill_interior,
+            },
+        )
+    }
+
+    fn solid_volume(grid: &VoxelGrid) -> f32 {
+        grid.solid_count() as f32 * grid.voxel_size.x * grid.voxel_size.y * grid.voxel_size.z
+    }
+
+    fn ball_volume(radius: f32) -> f32 {
+        4.0 / 3.0 * std::f32::consts::PI * radius * radius * radius
+    }
+
+    // Center sampling matches the volume, corner sampling stays inside the spheres but reaches within a voxel
+    // diagonal of their surface
+    fn assert_volume(grid: &VoxelGrid, sampling: Sampling, radii: &[f32]) {
+        let volume = solid_volume(grid);
+        let exact: f32 = radii.iter().map(|r| ball_volume(*r)).sum();
+
+        match sampling {
+            Sampling::Center => assert!((volume / exact - 1.0).abs() < 0.03, "{} voxelized, {} exact", volume, exact),
+            Sampling::Corners => {
+                let inner: f32 = radii.iter().map(|r| ball_volume(r - grid.voxel_diameter)).sum();
+                assert!(
+                    volume <= exact && volume >= inner,
+                    "{} voxelized, {}..{} expected",
+                    volume,
+                    inner,
+                    exact
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn single_sphere_volume() {
+        for &sampling in &[Sampling::Center, Sampling::Corners] {
+            let grid = voxelize(&[vec4(3.0, -1.0, 2.0, 10.0)], 64, sampling, false);
+            assert_volume(&grid, sampling, &[10.0]);
+
+            // The sphere is centered in the grid
+            let center = vec3(32, 32, 32);
+            assert!(grid.is_solid(center));
+            assert!(!grid.is_solid(vec3(0, 0, 0)));
+        }
+    }
+
+    #[test]
+    fn touching_spheres_volume() {
+        for &sampling in &[Sampling::Center, Sampling::Corners] {
+            let grid = voxelize(&[vec4(-5.0, 0.0, 0.0, 5.0), vec4(5.0, 0.0, 0.0, 5.0)], 96, sampling, false);
+            assert_volume(&grid, sampling, &[5.0, 5.0]);
+
+            // Sphere centers are a quarter of the grid away from its center along x
+            assert!(grid.is_solid(grid.snap(vec3(-5.0, 0.0, 0.0), Round::Floor)));
+            assert!(grid.is_solid(grid.snap(vec3(5.0, 0.0, 0.0), Round::Floor)));
+        }
+    }
+
+    #[test]
+    fn fill_interior_fills_a_hollow_shell() {
+        // Overlapping spheres on a sphere of radius 10 make a closed shell from radius 8 to 12
+        let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+        let shell: Vec<Vec4> = (0..2000)
+            .map(|i| {
+                let z = 1.0 - 2.0 * (i as f32 + 0.5) / 2000.0;
+                let radius = (1.0 - z * z).sqrt();
+                let angle = golden_angle * i as f32;
+                vec4(10.0 * radius * angle.cos(), 10.0 * radius * angle.sin(), 10.0 * z, 2.0)
+            })
+            .collect();
+
+        let hollow = voxelize(&shell, 64, Sampling::Center, false);
+        let filled = voxelize(&shell, 64, Sampling::Center, true);
+        let center = hollow.snap(vec3(0.0, 0.0, 0.0), Round::Floor);
+
+        assert!(!hollow.is_solid(center));
+        assert!(filled.is_solid(center));
+        assert!(!filled.is_solid(vec3(0, 0, 0)));
+
+        // Filling adds the inner ball and nothing outside the shell
+        let added = solid_volume(&filled) - solid_volume(&hollow);
+        assert!(
+            (added / ball_volume(8.0) - 1.0).abs() < 0.1,
+            "{} added, {} expected",
+            added,
+            ball_volume(8.0)
+        );
+        assert!(solid_volume(&filled) < ball_volume(12.0));
+    }
+
+    #[test]
+    fn planar_occluders_of_odd_sized_grids() {
+        for &contours in &[SliceContours::Voxels, SliceContours::Distance] {
+            let mut atoms = vec![vec4(2.0, -3.0, 1.0, 10.0)];
+            let mut grid = VoxelGrid::new(
+                &mut atoms,
+                &VoxelizationOptions {
+                    size: 33,
+                    sampling: Sampling::Center,
+                    fill_interior: true,
+                },
+            );
+            let triangles = grid.get_planar_occluders_with(100, contours);
+
+            // Slices are centered on the sphere and stay inside it
+            assert!(!triangles.is_empty() && triangles.len() % 3 == 0);
+            for vertex in &triangles {
+                assert!(
+                    glm::distance(&vertex.xyz(), &atoms[0].xyz()) <= 10.0 + grid.voxel_diameter,
+                    "{:?}",
+                    vertex
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn box_occluders_are_solid_and_disjoint() {
+        let spheres = [
+            vec4(-6.0, 0.0, 0.0, 6.0),
+            vec4(5.0, 2.0, -1.0, 4.0),
+            vec4(0.0, -4.0, 5.0, 3.0),
+            vec4(1.0, 6.0, 3.0, 2.5),
+        ];
+        let scattered = voxelize(&spheres, 64, Sampling::Center, false);
+
+        // Without any empty voxel every depth is infinite
+        let solid = voxelize(&[vec4(0.0, 0.0, 0.0, 10.0)], 2, Sampling::Center, true);
+        assert_eq!(solid.solid_count(), 8);
+
+        for (grid, min_volume) in &[(scattered, 8), (solid, 1)] {
+            let occluders = grid.get_box_occluders(32, *min_volume);
+
+            assert!(!occluders.is_empty() && occluders.len() <= 32);
+            for (i, a) in occluders.iter().enumerate() {
+                assert!(box_volume(a) >= *min_volume);
+                assert!(grid.is_box_solid(&grid.voxels, a), "box {:?} covers empty voxels", a);
+                for b in &occluders[i + 1..] {
+                    assert!(!boxes_overlap(a, b), "boxes {:?} and {:?} overlap", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn carving_lowers_depth_like_a_full_transform() {
+        let grid = voxelize(&[vec4(0.0, 0.0, 0.0, 10.0)], 48, Sampling::Center, false);
+        let size = grid.size as usize;
+        let transform = |voxels: &Voxels| {
+            let mut depth: Vec<f32> = (0..voxels.len())
+                .map(|i| if voxels.get(i) { std::f32::INFINITY } else { 0.0 })
+                .collect();
+            distance::squared_distance_transform(&mut depth, size, grid.voxel_size);
+            depth
+        };
+
+        let mut remaining = grid.voxels.clone();
+        let mut depth = transform(&remaining);
+        let reach = depth.iter().cloned().fold(0.0, f32::max).sqrt();
+        for voxel_box in &[(vec3(20, 20, 20), vec3(27, 25, 23)), (vec3(12, 22, 22), vec3(17, 24, 30))] {
+            for z in voxel_box.0.z..=voxel_box.1.z {
+                for y in voxel_box.0.y..=voxel_box.1.y {
+                    for x in voxel_box.0.x..=voxel_box.1.x {
+                        remaining.set(grid.to_1d(vec3(x, y, z)), false);
+                    }
+                }
+            }
+            grid.carve_depth(&mut depth, voxel_box, reach);
+        }
+
+        for (carved, full) in depth.iter().zip(transform(&remaining)) {
+            assert!(
+                (carved - full).abs() <= 1e-3 * full.max(1.0),
+                "{} carved, {} expected",
+                carved,
+                full
+            );
+        }
+    }
+}
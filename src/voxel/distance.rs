@@ -0,0 +1,229 @@
+//! Exact Euclidean distance transform of a voxel grid (Felzenszwalb and Huttenlocher, "Distance Transforms
+//! of Sampled Functions").
+//!
+//! The squared distance is computed by three separable passes of the 1D transform along x, y and z, each
+//! taking the lower envelope of the parabolas rooted at the feature voxels. Distances are measured between
+//! voxel centers in world units, so voxels need not be cubic.
+use super::VoxelGrid;
+use glm::Vec3;
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+
+/// Signed distance of every voxel of a grid: positive for empty voxels (distance to the nearest solid voxel),
+/// negative for solid voxels (minus the distance to the nearest empty voxel). Indexed like the grid.
+pub struct DistanceField {
+    pub size: i32,
+    pub distances: Vec<f32>,
+}
+
+impl DistanceField {
+    pub fn get(&self, input: glm::TVec3<i32>) -> f32 {
+        let size = self.size as usize;
+        self.distances[(input.z as usize * size + input.y as usize) * size + input.x as usize]
+    }
+
+    /// Distance at a continuous grid position (voxel `v` spans `[v, v + 1]`), interpolated trilinearly between the
+    /// voxel centers. Positions beyond the outer voxel centers take the distance of the border.
+    pub fn sample(&self, position: Vec3) -> f32 {
+        let last = self.size - 1;
+        let clamp = |c: f32| (c - 0.5).max(0.0).min(last as f32);
+        let position = [clamp(position.x), clamp(position.y), clamp(position.z)];
+
+        let mut base = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            base[axis] = (position[axis].floor() as i32).min(last - 1).max(0);
+            fraction[axis] = position[axis] - base[axis] as f32;
+        }
+
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let mut voxel = [0; 3];
+            let mut weight = 1.0;
+            for axis in 0..3 {
+                if corner & (1 << axis) == 0 {
+                    voxel[axis] = base[axis];
+                    weight *= 1.0 - fraction[axis];
+                } else {
+                    voxel[axis] = (base[axis] + 1).min(last);
+                    weight *= fraction[axis];
+                }
+            }
+            if weight > 0.0 {
+                value += weight * self.get(glm::vec3(voxel[0], voxel[1], voxel[2]));
+            }
+        }
+
+        value
+    }
+}
+
+// Lower envelope of parabolas, reused between lines
+#[derive(Default)]
+struct Envelope {
+    // Feature positions of the parabolas
+    vertices: Vec<f32>,
+    // Their heights
+    heights: Vec<f32>,
+    // Left boundaries of the intervals where each parabola is the lowest
+    boundaries: Vec<f32>,
+}
+
+impl Envelope {
+    // Replaces `line[p]` by `min_q (line[q] + ((p - q) * spacing)²)`. Infinite values are not features.
+    fn transform(&mut self, line: &mut [f32], spacing: f32) {
+        self.vertices.clear();
+        self.heights.clear();
+        self.boundaries.clear();
+
+        for (q, &height) in line.iter().enumerate() {
+            if height == std::f32::INFINITY {
+                continue;
+            }
+
+            let vertex = q as f32 * spacing;
+            while let Some(&last) = self.vertices.last() {
+                let last_height = *self.heights.last().unwrap();
+                let intersection = ((height + vertex * vertex) - (last_height + last * last)) / (2.0 * (vertex - last));
+
+                if intersection <= *self.boundaries.last().unwrap() {
+                    self.vertices.pop();
+                    self.heights.pop();
+                    self.boundaries.pop();
+                } else {
+                    self.boundaries.push(intersection);
+                    break;
+                }
+            }
+            if self.vertices.is_empty() {
+                self.boundaries.push(std::f32::NEG_INFINITY);
+            }
+            self.vertices.push(vertex);
+            self.heights.push(height);
+        }
+
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let mut k = 0;
+        for (p, value) in line.iter_mut().enumerate() {
+            let position = p as f32 * spacing;
+            while k + 1 < self.vertices.len() && self.boundaries[k + 1] < position {
+                k += 1;
+            }
+
+            let d = position - self.vertices[k];
+            *value = d * d + self.heights[k];
+        }
+    }
+}
+
+/// Squared distance from every voxel to the nearest feature voxel. `field` holds `0.0` for the features and
+/// infinity elsewhere and is transformed in place. Voxels of a grid without any feature stay infinite.
+pub fn squared_distance_transform(field: &mut [f32], size: usize, voxel_size: Vec3) {
+    assert_eq!(field.len(), size * size * size);
+    if size == 0 {
+        return;
+    }
+    let slice = size * size;
+
+    // x and y passes stay within a z-slice
+    field
+        .par_chunks_mut(slice)
+        .for_each_init(Envelope::default, |envelope, slice_values| {
+            for row in slice_values.chunks_mut(size) {
+                envelope.transform(row, voxel_size.x);
+            }
+
+            let mut column = vec![0.0; size];
+            for x in 0..size {
+                for y in 0..size {
+                    column[y] = slice_values[y * size + x];
+                }
+                envelope.transform(&mut column, voxel_size.y);
+                for y in 0..size {
+                    slice_values[y * size + x] = column[y];
+                }
+            }
+        });
+
+    // z pass, every task transforms the columns of one y. Batches of y keep the copies small.
+    let batch = 4 * rayon::current_num_threads();
+    for y_start in (0..size).step_by(batch) {
+        let columns: Vec<Vec<f32>> = (y_start..(y_start + batch).min(size))
+            .into_par_iter()
+            .map_init(Envelope::default, |envelope, y| {
+                let mut columns = vec![0.0; size * size];
+                for x in 0..size {
+                    let column = &mut columns[x * size..(x + 1) * size];
+                    for (z, value) in column.iter_mut().enumerate() {
+                        *value = field[z * slice + y * size + x];
+                    }
+                    envelope.transform(column, voxel_size.z);
+                }
+
+                columns
+            })
+            .collect();
+
+        for (y, columns) in (y_start..).zip(columns.iter()) {
+            for x in 0..size {
+                for z in 0..size {
+                    field[z * slice + y * size + x] = columns[x * size + z];
+                }
+            }
+        }
+    }
+}
+
+impl VoxelGrid {
+    /// Signed Euclidean distance transform of the grid. Needs two `f32` per voxel while it runs.
+    pub fn distance_transform(&self) -> DistanceField {
+        let size = self.size as usize;
+        let len = self.voxels.len();
+
+        // Distance of empty voxels to the solid ones and of solid voxels to the empty ones
+        let feature_field = |solid_features: bool| {
+            let mut field: Vec<f32> = (0..len)
+                .into_par_iter()
+                .map(|i| {
+                    if self.voxels.get(i) == solid_features {
+                        0.0
+                    } else {
+                        std::f32::INFINITY
+                    }
+                })
+                .collect();
+            squared_distance_transform(&mut field, size, self.voxel_size);
+            field
+        };
+
+        let mut distances = feature_field(true);
+        let inside = feature_field(false);
+        distances.par_iter_mut().zip(inside.par_iter()).for_each(|(distance, inside)| {
+            *distance = distance.sqrt() - inside.sqrt();
+        });
+
+        DistanceField {
+            size: self.size,
+            distances,
+        }
+    }
+
+    /// Signed distance at a world space position, see `DistanceField::sample`. Everything outside the bounding box
+    /// of the grid is infinitely far outside.
+    pub fn sample_distance(&self, field: &DistanceField, position: Vec3) -> f32 {
+        let inside = (0..3).all(|axis| position[axis] > self.bb_min[axis] && position[axis] < self.bb_max[axis]);
+        if !inside {
+            return std::f32::INFINITY;
+        }
+
+        let grid_position = position - self.bb_min;
+        field.sample(glm::vec3(
+            grid_position.x / self.voxel_size.x,
+            grid_position.y / self.voxel_size.y,
+            grid_position.z / self.voxel_size.z,
+        ))
+    }
+}
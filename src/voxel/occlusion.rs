@@ -0,0 +1,360 @@
+//! Occlusion of a voxelized molecule by its occluders, measured in orthographic views from many directions.
+//!
+//! Every view rasterizes the silhouette of the solid voxels into a square bitmask around the bounding sphere of
+//! the grid. Occluder triangles are rasterized into the same views, the occlusion of a view is the fraction of the
+//! silhouette pixels they cover. Pixels they cover outside the silhouette would hide what is behind the molecule
+//! and are reported as false occlusion. Opposite directions see mirrored silhouettes, so only a hemisphere is
+//! sampled.
+use super::VoxelGrid;
+use glm::{vec2, vec3, Vec2, Vec3};
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+#[derive(Clone, Debug)]
+pub struct OcclusionOptions {
+    /// Number of view directions.
+    pub directions: usize,
+    /// Pixels along both sides of a view, capped to the grid size so that voxels do not leave holes in the
+    /// silhouette.
+    pub resolution: usize,
+}
+
+impl Default for OcclusionOptions {
+    fn default() -> Self {
+        Self {
+            directions: 64,
+            resolution: 128,
+        }
+    }
+}
+
+/// Occlusion of one view, or averaged over the views.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Occlusion {
+    /// Fraction of the silhouette pixels covered by the occluders.
+    pub hidden: f32,
+    /// Fraction of the pixels covered by the occluders that are outside the silhouette.
+    pub false_occlusion: f32,
+}
+
+struct View {
+    right: Vec3,
+    up: Vec3,
+    silhouette: Vec<u64>,
+    silhouette_len: usize,
+}
+
+fn get_bit(bits: &[u64], index: usize) -> bool {
+    bits[index / 64] & (1 << (index % 64)) != 0
+}
+
+fn set_bit(bits: &mut [u64], index: usize) {
+    bits[index / 64] |= 1 << (index % 64);
+}
+
+// Evenly spread directions on the upper hemisphere (Fibonacci lattice)
+fn hemisphere_directions(count: usize) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+
+    (0..count)
+        .map(|i| {
+            let z = 1.0 - (i as f32 + 0.5) / count as f32;
+            let radius = (1.0 - z * z).sqrt();
+            let angle = golden_angle * i as f32;
+
+            vec3(radius * angle.cos(), radius * angle.sin(), z)
+        })
+        .collect()
+}
+
+/// Twelve triangles of the faces of a box.
+pub fn box_triangles(min: &Vec3, max: &Vec3) -> Vec<Vec3> {
+    let corner = |i: usize| {
+        vec3(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        )
+    };
+    let faces = [[0, 1, 3, 2], [4, 5, 7, 6], [0, 1, 5, 4], [2, 3, 7, 6], [0, 2, 6, 4], [1, 3, 7, 5]];
+
+    let mut triangles = Vec::with_capacity(36);
+    for face in &faces {
+        for &i in &[face[0], face[1], face[2], face[0], face[2], face[3]] {
+            triangles.push(corner(i));
+        }
+    }
+
+    triangles
+}
+
+pub struct OcclusionEvaluator {
+    views: Vec<View>,
+    // Views are centered on the bounding sphere of the grid
+    center: Vec3,
+    resolution: usize,
+    radius: f32,
+    pixel_size: f32,
+}
+
+impl OcclusionEvaluator {
+    /// Rasterizes the silhouettes of the grid. Occluders are given in the world space of the grid.
+    pub fn new(grid: &VoxelGrid, options: &OcclusionOptions) -> Self {
+        let resolution = options.resolution.min(grid.size as usize).max(1);
+        let radius = glm::length(&grid.bb_diff) / 2.0;
+        let pixel_size = 2.0 * radius / resolution as f32;
+
+        // Only solid voxels next to an empty one can be on the silhouette
+        let neighbours = [
+            vec3(1, 0, 0),
+            vec3(-1, 0, 0),
+            vec3(0, 1, 0),
+            vec3(0, -1, 0),
+            vec3(0, 0, 1),
+            vec3(0, 0, -1),
+        ];
+        let surface: Vec<Vec3> = (0..grid.voxels.len())
+            .into_par_iter()
+            .filter(|&i| grid.voxels.get(i))
+            .map(|i| grid.to_3d(i))
+            .filter(|position| neighbours.iter().any(|offset| !grid.is_solid(position + offset)))
+            .map(|position| grid.to_ws(position))
+            .collect();
+
+        let mut evaluator = Self {
+            views: Vec::new(),
+            center: (grid.bb_min + grid.bb_max) / 2.0,
+            resolution,
+            radius,
+            pixel_size,
+        };
+
+        let views = hemisphere_directions(options.directions)
+            .into_par_iter()
+            .map(|direction| {
+                let helper = if direction.z.abs() < 0.9 {
+                    vec3(0.0, 0.0, 1.0)
+                } else {
+                    vec3(1.0, 0.0, 0.0)
+                };
+                let right = glm::normalize(&glm::cross(&helper, &direction));
+                let up = glm::cross(&direction, &right);
+
+                let mut view = View {
+                    right,
+                    up,
+                    silhouette: vec![0; (resolution * resolution + 63) / 64],
+                    silhouette_len: 0,
+                };
+                for position in &surface {
+                    if let Some(pixel) = evaluator.pixel(&evaluator.project(&view, position)) {
+                        set_bit(&mut view.silhouette, pixel);
+                    }
+                }
+                view.silhouette_len = view.silhouette.iter().map(|word| word.count_ones() as usize).sum();
+
+                view
+            })
+            .collect();
+        evaluator.views = views;
+
+        evaluator
+    }
+
+    pub fn views_len(&self) -> usize {
+        self.views.len()
+    }
+
+    // Continuous pixel coordinates of a point
+    fn project(&self, view: &View, position: &Vec3) -> Vec2 {
+        let position = position - self.center;
+        vec2(
+            (glm::dot(&position, &view.right) + self.radius) / self.pixel_size,
+            (glm::dot(&position, &view.up) + self.radius) / self.pixel_size,
+        )
+    }
+
+    fn pixel(&self, point: &Vec2) -> Option<usize> {
+        let (x, y) = (point.x.floor(), point.y.floor());
+        if x < 0.0 || y < 0.0 || x >= self.resolution as f32 || y >= self.resolution as f32 {
+            None
+        } else {
+            Some(y as usize * self.resolution + x as usize)
+        }
+    }
+
+    // Calls `f` for every pixel whose center is inside the projected triangle
+    fn rasterize(&self, view: &View, triangle: &[Vec3], mut f: impl FnMut(usize)) {
+        let p: Vec<Vec2> = triangle.iter().map(|v| self.project(view, v)).collect();
+        let edge = |a: &Vec2, b: &Vec2, c: &Vec2| (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+
+        let area = edge(&p[0], &p[1], &p[2]);
+        if area == 0.0 {
+            return;
+        }
+
+        let last = self.resolution as f32 - 1.0;
+        let min = glm::min2(&glm::min2(&p[0], &p[1]), &p[2]);
+        let max = glm::max2(&glm::max2(&p[0], &p[1]), &p[2]);
+        let (min_x, min_y) = ((min.x - 0.5).ceil().max(0.0), (min.y - 0.5).ceil().max(0.0));
+        let (max_x, max_y) = ((max.x - 0.5).floor().min(last), (max.y - 0.5).floor().min(last));
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        for y in min_y as usize..=max_y as usize {
+            for x in min_x as usize..=max_x as usize {
+                let center = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                let w = [
+                    edge(&p[1], &p[2], &center),
+                    edge(&p[2], &p[0], &center),
+                    edge(&p[0], &p[1], &center),
+                ];
+
+                let inside = if area > 0.0 {
+                    w.iter().all(|w| *w >= 0.0)
+                } else {
+                    w.iter().all(|w| *w <= 0.0)
+                };
+                if inside {
+                    f(y * self.resolution + x);
+                }
+            }
+        }
+    }
+
+    /// Occlusion of a triangle soup (three vertices per triangle) in every view.
+    pub fn occlusion(&self, triangles: &[Vec3]) -> Vec<Occlusion> {
+        self.views
+            .par_iter()
+            .map(|view| {
+                let mut covered = vec![0u64; view.silhouette.len()];
+                for triangle in triangles.chunks_exact(3) {
+                    self.rasterize(view, triangle, |pixel| set_bit(&mut covered, pixel));
+                }
+
+                let (mut hidden, mut outside) = (0, 0);
+                for (covered, silhouette) in covered.iter().zip(view.silhouette.iter()) {
+                    hidden += (covered & silhouette).count_ones() as usize;
+                    outside += (covered & !silhouette).count_ones() as usize;
+                }
+                Occlusion {
+                    hidden: hidden as f32 / view.silhouette_len.max(1) as f32,
+                    false_occlusion: outside as f32 / (hidden + outside).max(1) as f32,
+                }
+            })
+            .collect()
+    }
+
+    /// Occlusion averaged over the views.
+    pub fn mean_occlusion(&self, triangles: &[Vec3]) -> Occlusion {
+        let occlusion = self.occlusion(triangles);
+        let views = occlusion.len().max(1) as f32;
+        Occlusion {
+            hidden: occlusion.iter().map(|o| o.hidden).sum::<f32>() / views,
+            false_occlusion: occlusion.iter().map(|o| o.false_occlusion).sum::<f32>() / views,
+        }
+    }
+
+    // Silhouette pixels of all views covered by a triangle and not yet by the taken ones, minus all the pixels it
+    // covers outside the silhouette. Charging every outside pixel keeps the gain from growing as triangles are taken.
+    fn gain(&self, triangle: &[Vec3], covered: &[Vec<u64>]) -> isize {
+        let mut gain = 0;
+        for (view, covered) in self.views.iter().zip(covered.iter()) {
+            self.rasterize(view, triangle, |pixel| {
+                if !get_bit(&view.silhouette, pixel) {
+                    gain -= 1;
+                } else if !get_bit(covered, pixel) {
+                    gain += 1;
+                }
+            });
+        }
+
+        gain
+    }
+
+    /// Picks at most `budget` triangles of a triangle soup, every one hiding the most silhouette pixels not hidden
+    /// by the triangles picked before it, less the pixels it covers outside the silhouette. Triangles that do not
+    /// hide more than they falsely cover are never picked. Returns the indices of the triangles in the order they
+    /// were picked.
+    pub fn select_triangles(&self, triangles: &[Vec3], budget: usize) -> Vec<usize> {
+        let mut covered: Vec<Vec<u64>> = self.views.iter().map(|view| vec![0; view.silhouette.len()]).collect();
+        let gains: Vec<isize> = triangles
+            .par_chunks_exact(3)
+            .map(|triangle| self.gain(triangle, &covered))
+            .collect();
+
+        // Gains only shrink as triangles are picked, so a stale gain is an upper bound (lazy greedy)
+        let mut queue: BinaryHeap<(isize, Reverse<usize>, usize)> = gains
+            .iter()
+            .enumerate()
+            .filter(|(_, gain)| **gain > 0)
+            .map(|(i, gain)| (*gain, Reverse(i), 0))
+            .collect();
+
+        let mut selected = Vec::new();
+        while let Some((gain, Reverse(i), computed_at)) = queue.pop() {
+            if selected.len() >= budget {
+                break;
+            }
+
+            let triangle = &triangles[i * 3..i * 3 + 3];
+            if computed_at < selected.len() {
+                let gain = self.gain(triangle, &covered);
+                if gain > 0 {
+                    queue.push((gain, Reverse(i), selected.len()));
+                }
+                continue;
+            }
+
+            debug_assert!(gain > 0);
+            for (view, covered) in self.views.iter().zip(covered.iter_mut()) {
+                self.rasterize(view, triangle, |pixel| {
+                    if get_bit(&view.silhouette, pixel) {
+                        set_bit(covered, pixel);
+                    }
+                });
+            }
+            selected.push(i);
+        }
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::VoxelizationOptions;
+
+    #[test]
+    fn triangles_outside_the_silhouette_are_false_occlusion() {
+        let mut atoms = vec![glm::vec4(0.0, 0.0, 0.0, 10.0)];
+        let grid = VoxelGrid::new(
+            &mut atoms,
+            &VoxelizationOptions {
+                size: 32,
+                ..Default::default()
+            },
+        );
+        let evaluator = OcclusionEvaluator::new(&grid, &OcclusionOptions::default());
+
+        // A small triangle inside the sphere hides part of it, a huge one hides it all but covers the background too
+        let inside = [vec3(-2.0, -2.0, 0.0), vec3(2.0, -2.0, 0.0), vec3(0.0, 2.0, 0.0)];
+        let huge = [vec3(-1000.0, -1000.0, 0.0), vec3(1000.0, -1000.0, 0.0), vec3(0.0, 1000.0, 0.0)];
+
+        let occlusion = evaluator.mean_occlusion(&inside);
+        assert!(occlusion.hidden > 0.0 && occlusion.hidden < 1.0);
+        assert_eq!(occlusion.false_occlusion, 0.0);
+
+        // Views are not all facing the triangle plane, so only the views that see it well are fully hidden
+        let occlusion = evaluator.mean_occlusion(&huge);
+        assert!(occlusion.hidden > 0.5);
+        assert!(occlusion.false_occlusion > 0.5);
+
+        let triangles: Vec<Vec3> = huge.iter().chain(inside.iter()).cloned().collect();
+        assert_eq!(evaluator.select_triangles(&triangles, 2), vec![1]);
+    }
+}
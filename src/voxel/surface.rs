@@ -0,0 +1,310 @@
+//! Surface of a voxel grid extracted from its distance field with surface nets (Gibson, "Constrained Elastic
+//! Surface Nets"), the simplest form of dual contouring.
+//!
+//! The field is sampled at the voxel centers. Every cell between eight samples that the iso-surface crosses gets
+//! one vertex, the average of the crossings on its edges, and every crossed edge between two samples gets a quad
+//! joining the vertices of the four cells around it. Samples beyond the grid are outside, so the surface is closed.
+use super::distance::DistanceField;
+use super::VoxelGrid;
+use glm::{vec3, Vec3};
+use nalgebra_glm as glm;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// Indexed triangle mesh in the world space of a grid. Triangles are counterclockwise seen from the outside and
+/// normals point outside.
+#[derive(Clone, Debug, Default)]
+pub struct SurfaceMesh {
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl SurfaceMesh {
+    pub fn triangles_len(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Splits the mesh into meshes of at most `max_vertices` vertices each (at least 3), e.g. for 16-bit indices.
+    /// Vertices shared by triangles of different parts are duplicated.
+    pub fn split(&self, max_vertices: usize) -> Vec<SurfaceMesh> {
+        assert!(max_vertices >= 3);
+
+        let mut meshes = Vec::new();
+        let mut mesh = SurfaceMesh::default();
+        let mut remap: HashMap<u32, u32> = HashMap::new();
+        for triangle in self.indices.chunks_exact(3) {
+            let new_vertices = triangle.iter().filter(|index| !remap.contains_key(index)).count();
+            if mesh.vertices.len() + new_vertices > max_vertices {
+                meshes.push(std::mem::take(&mut mesh));
+                remap.clear();
+            }
+
+            for &index in triangle {
+                let remapped = *remap.entry(index).or_insert_with(|| {
+                    mesh.vertices.push(self.vertices[index as usize]);
+                    mesh.normals.push(self.normals[index as usize]);
+                    mesh.vertices.len() as u32 - 1
+                });
+                mesh.indices.push(remapped);
+            }
+        }
+        if !mesh.indices.is_empty() {
+            meshes.push(mesh);
+        }
+
+        meshes
+    }
+}
+
+// Crossing of the iso-surface on the edge between two samples, see `contour::iso_contours`
+fn edge_crossing(a: Vec3, b: Vec3, value_a: f32, value_b: f32, iso: f32) -> Vec3 {
+    let t = if value_a.is_finite() && value_b.is_finite() {
+        ((iso - value_a) / (value_b - value_a)).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+
+    a + (b - a) * t
+}
+
+impl VoxelGrid {
+    /// Surface of the solid voxels, see `surface_mesh_with`.
+    pub fn surface_mesh(&self) -> SurfaceMesh {
+        self.surface_mesh_with(&self.distance_transform(), 0.0)
+    }
+
+    /// Iso-surface of a distance field of this grid at `iso` (in world units). Zero follows the faces of the solid
+    /// voxels with rounded edges, negative values shrink the surface inside the voxels.
+    pub fn surface_mesh_with(&self, field: &DistanceField, iso: f32) -> SurfaceMesh {
+        assert_eq!(field.size, self.size);
+        let size = self.size;
+
+        // Samples `-1..=size` along every axis, the ones beyond the grid are infinitely far outside
+        let sample = |x: i32, y: i32, z: i32| {
+            let voxel = vec3(x, y, z);
+            if self.contains(voxel) {
+                field.get(voxel)
+            } else {
+                std::f32::INFINITY
+            }
+        };
+
+        // Cell `c` spans the samples `c - 1` and `c` along every axis, there are `size + 1` of them per axis
+        let cells = (size + 1) as usize;
+        let cell_index = |x: i32, y: i32, z: i32| (z as usize * cells + y as usize) * cells + x as usize;
+
+        // Vertices of the crossed cells in grid coordinates (voxel `v` spans `[v, v + 1]`), sorted by cell
+        let slices: Vec<Vec<(usize, Vec3)>> = (0..=size)
+            .into_par_iter()
+            .map(|z| {
+                let mut vertices = Vec::new();
+                for y in 0..=size {
+                    for x in 0..=size {
+                        let mut values = [0.0; 8];
+                        let mut inside_len = 0;
+                        for (corner, value) in values.iter_mut().enumerate() {
+                            *value = sample(
+                                x - 1 + (corner & 1) as i32,
+                                y - 1 + ((corner >> 1) & 1) as i32,
+                                z - 1 + ((corner >> 2) & 1) as i32,
+                            );
+                            if *value < iso {
+                                inside_len += 1;
+                            }
+                        }
+                        if inside_len == 0 || inside_len == 8 {
+                            continue;
+                        }
+
+                        let position = |corner: usize| {
+                            vec3(
+                                x as f32 - 0.5 + (corner & 1) as f32,
+                                y as f32 - 0.5 + ((corner >> 1) & 1) as f32,
+                                z as f32 - 0.5 + ((corner >> 2) & 1) as f32,
+                            )
+                        };
+
+                        // Edges join the corners that differ in one bit
+                        let mut sum = vec3(0.0, 0.0, 0.0);
+                        let mut crossings = 0;
+                        for a in 0..8 {
+                            for axis in 0..3 {
+                                let b = a | (1 << axis);
+                                if b == a || (values[a] < iso) == (values[b] < iso) {
+                                    continue;
+                                }
+
+                                sum += edge_crossing(position(a), position(b), values[a], values[b], iso);
+                                crossings += 1;
+                            }
+                        }
+
+                        vertices.push((cell_index(x, y, z), sum / crossings as f32));
+                    }
+                }
+
+                vertices
+            })
+            .collect();
+        let (cells_crossed, positions): (Vec<usize>, Vec<Vec3>) = slices.into_iter().flatten().unzip();
+
+        // Quads of the crossed edges from every sample to the next one along each axis
+        let indices: Vec<u32> = (-1..size)
+            .into_par_iter()
+            .map(|z| {
+                let mut indices = Vec::new();
+                let mut quad = [0u32; 4];
+                for y in -1..size {
+                    for x in -1..size {
+                        let value = sample(x, y, z);
+                        for axis in 0..3 {
+                            let mut next = [x, y, z];
+                            next[axis] += 1;
+
+                            let inside = value < iso;
+                            if inside == (sample(next[0], next[1], next[2]) < iso) {
+                                continue;
+                            }
+
+                            // Cells around the edge, counterclockwise around the axis
+                            let (u, w) = ((axis + 1) % 3, (axis + 2) % 3);
+                            for (corner, (du, dw)) in [(0, 0), (1, 0), (1, 1), (0, 1)].iter().enumerate() {
+                                let mut cell = [x, y, z];
+                                cell[axis] += 1;
+                                cell[u] += du;
+                                cell[w] += dw;
+
+                                let cell = cell_index(cell[0], cell[1], cell[2]);
+                                quad[corner] = cells_crossed
+                                    .binary_search(&cell)
+                                    .expect("Cell around a crossed edge has no vertex")
+                                    as u32;
+                            }
+
+                            // The quad faces the outside, along the axis when the sample is inside
+                            if !inside {
+                                quad.reverse();
+                            }
+                            indices.extend_from_slice(&[quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+                        }
+                    }
+                }
+
+                indices
+            })
+            .flatten()
+            .collect();
+
+        // Normals follow the gradient of the field, which grows outwards
+        let to_ws = |position: &Vec3| self.bb_min + position.component_mul(&self.voxel_size);
+        let normals = positions
+            .par_iter()
+            .map(|position| {
+                let mut gradient = vec3(0.0, 0.0, 0.0);
+                for axis in 0..3 {
+                    let mut offset = vec3(0.0, 0.0, 0.0);
+                    offset[axis] = 0.5;
+                    gradient[axis] = (field.sample(position + offset) - field.sample(position - offset)) / self.voxel_size[axis];
+                }
+
+                if glm::length(&gradient) > 0.0 {
+                    glm::normalize(&gradient)
+                } else {
+                    gradient
+                }
+            })
+            .collect();
+
+        SurfaceMesh {
+            vertices: positions.iter().map(to_ws).collect(),
+            normals,
+            indices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::{Sampling, VoxelizationOptions};
+
+    // Sphere of radius 10 voxelized in a 48³ grid, returned with its center once the grid has centered it
+    fn sphere_grid() -> (VoxelGrid, Vec3) {
+        let mut atoms = vec![glm::vec4(4.0, -2.0, 7.0, 10.0)];
+        let grid = VoxelGrid::new(
+            &mut atoms,
+            &VoxelizationOptions {
+                size: 48,
+                sampling: Sampling::Center,
+                fill_interior: false,
+            },
+        );
+
+        (grid, atoms[0].xyz())
+    }
+
+    #[test]
+    fn sphere_surface_is_watertight() {
+        let (grid, _) = sphere_grid();
+        let mesh = grid.surface_mesh();
+        assert!(mesh.triangles_len() > 0);
+
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        assert!(edges.values().all(|&count| count == 2));
+    }
+
+    #[test]
+    fn sphere_surface_follows_the_sphere() {
+        let (grid, center) = sphere_grid();
+        let mesh = grid.surface_mesh();
+
+        for vertex in &mesh.vertices {
+            let distance = glm::distance(vertex, &center);
+            assert!(
+                (distance - 10.0).abs() <= grid.voxel_diameter,
+                "vertex {} from the center",
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn split_keeps_every_triangle() {
+        let (grid, _) = sphere_grid();
+        let mesh = grid.surface_mesh();
+
+        let triangle = |mesh: &SurfaceMesh, indices: &[u32]| -> Vec<[i64; 3]> {
+            indices
+                .iter()
+                .map(|&i| {
+                    let v = mesh.vertices[i as usize] * 1024.0;
+                    [v.x as i64, v.y as i64, v.z as i64]
+                })
+                .collect()
+        };
+        let mut expected: Vec<Vec<[i64; 3]>> = mesh.indices.chunks_exact(3).map(|t| triangle(&mesh, t)).collect();
+        expected.sort();
+
+        for &max_vertices in &[std::u16::MAX as usize + 1, 1000] {
+            let parts = mesh.split(max_vertices);
+            assert!(max_vertices < mesh.vertices.len() || parts.len() == 1);
+
+            let mut triangles = Vec::new();
+            for part in &parts {
+                assert!(part.vertices.len() <= max_vertices);
+                assert_eq!(part.vertices.len(), part.normals.len());
+                assert!(part.indices.iter().all(|&i| (i as usize) < part.vertices.len()));
+                triangles.extend(part.indices.chunks_exact(3).map(|t| triangle(part, t)));
+            }
+            triangles.sort();
+            assert_eq!(triangles, expected);
+        }
+    }
+}